@@ -0,0 +1,45 @@
+-- Baseline schema. Written to be idempotent so databases created before
+-- versioned migrations (by the old startup DDL) are adopted as-is.
+
+CREATE TABLE IF NOT EXISTS location_logs (
+    id TEXT PRIMARY KEY,
+    device TEXT NOT NULL,
+    state TEXT NOT NULL,
+    station_id INTEGER,
+    line_id INTEGER NOT NULL,
+    segment_id TEXT,
+    from_station_id INTEGER,
+    to_station_id INTEGER,
+    latitude DOUBLE PRECISION NOT NULL,
+    longitude DOUBLE PRECISION NOT NULL,
+    accuracy DOUBLE PRECISION,
+    speed DOUBLE PRECISION,
+    timestamp BIGINT NOT NULL,
+    battery_level DOUBLE PRECISION,
+    battery_state SMALLINT,
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Columns added after the first release.
+ALTER TABLE location_logs ADD COLUMN IF NOT EXISTS station_id INTEGER;
+ALTER TABLE location_logs ADD COLUMN IF NOT EXISTS line_id INTEGER;
+ALTER TABLE location_logs ADD COLUMN IF NOT EXISTS segment_id TEXT;
+ALTER TABLE location_logs ADD COLUMN IF NOT EXISTS from_station_id INTEGER;
+ALTER TABLE location_logs ADD COLUMN IF NOT EXISTS to_station_id INTEGER;
+ALTER TABLE location_logs ALTER COLUMN speed DROP NOT NULL;
+ALTER TABLE location_logs ADD COLUMN IF NOT EXISTS battery_level DOUBLE PRECISION;
+ALTER TABLE location_logs ADD COLUMN IF NOT EXISTS battery_state SMALLINT;
+
+CREATE TABLE IF NOT EXISTS log_events (
+    id TEXT PRIMARY KEY,
+    device TEXT NOT NULL,
+    log_type TEXT NOT NULL,
+    log_level TEXT NOT NULL,
+    message TEXT NOT NULL,
+    timestamp BIGINT NOT NULL,
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_location_logs_device ON location_logs (device);
+CREATE INDEX IF NOT EXISTS idx_location_logs_segment ON location_logs (segment_id);
+CREATE INDEX IF NOT EXISTS idx_log_events_device ON log_events (device);
@@ -0,0 +1,115 @@
+-- Partition both event tables by a TIMESTAMPTZ event time derived from the
+-- client's millisecond `timestamp`, so time-range queries prune partitions
+-- and retention can drop whole partitions.
+--
+-- Existing rows are copied into monthly partitions. The server creates the
+-- partitions for new data (daily or monthly, see `partition_interval`) and
+-- drops expired ones; anything outside every range lands in the DEFAULT
+-- partition.
+
+ALTER TABLE location_logs RENAME TO location_logs_legacy;
+ALTER INDEX IF EXISTS location_logs_pkey RENAME TO location_logs_legacy_pkey;
+DROP INDEX IF EXISTS idx_location_logs_device;
+DROP INDEX IF EXISTS idx_location_logs_segment;
+
+ALTER TABLE log_events RENAME TO log_events_legacy;
+ALTER INDEX IF EXISTS log_events_pkey RENAME TO log_events_legacy_pkey;
+DROP INDEX IF EXISTS idx_log_events_device;
+
+-- The partition key must be part of the primary key. This narrows
+-- deduplication: `ON CONFLICT DO NOTHING` now only collapses rows whose id
+-- AND event_time match. Spool replays and retries resend identical rows and
+-- stay harmless, but an event resent under the same id with a different
+-- timestamp is stored twice. Ids are no longer unique on their own.
+CREATE TABLE location_logs (
+    id TEXT NOT NULL,
+    device TEXT NOT NULL,
+    state TEXT NOT NULL,
+    station_id INTEGER,
+    line_id INTEGER,
+    segment_id TEXT,
+    from_station_id INTEGER,
+    to_station_id INTEGER,
+    latitude DOUBLE PRECISION NOT NULL,
+    longitude DOUBLE PRECISION NOT NULL,
+    accuracy DOUBLE PRECISION,
+    speed DOUBLE PRECISION,
+    timestamp BIGINT NOT NULL,
+    event_time TIMESTAMPTZ NOT NULL,
+    battery_level DOUBLE PRECISION,
+    battery_state SMALLINT,
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (id, event_time)
+) PARTITION BY RANGE (event_time);
+
+CREATE TABLE log_events (
+    id TEXT NOT NULL,
+    device TEXT NOT NULL,
+    log_type TEXT NOT NULL,
+    log_level TEXT NOT NULL,
+    message TEXT NOT NULL,
+    timestamp BIGINT NOT NULL,
+    event_time TIMESTAMPTZ NOT NULL,
+    recorded_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (id, event_time)
+) PARTITION BY RANGE (event_time);
+
+CREATE TABLE location_logs_default PARTITION OF location_logs DEFAULT;
+CREATE TABLE log_events_default PARTITION OF log_events DEFAULT;
+
+-- Monthly partitions covering existing rows since 2000, up to the current month.
+DO $$
+DECLARE
+    tbl TEXT;
+    month TIMESTAMP;
+    this_month TIMESTAMP := date_trunc('month', now() AT TIME ZONE 'UTC');
+BEGIN
+    FOREACH tbl IN ARRAY ARRAY['location_logs', 'log_events'] LOOP
+        EXECUTE format(
+            'SELECT date_trunc(''month'', to_timestamp(min(timestamp) / 1000.0) AT TIME ZONE ''UTC'')
+               FROM %I WHERE timestamp >= 946684800000',
+            tbl || '_legacy'
+        ) INTO month;
+        WHILE month IS NOT NULL AND month <= this_month LOOP
+            EXECUTE format(
+                'CREATE TABLE %I PARTITION OF %I FOR VALUES FROM (%L) TO (%L)',
+                tbl || '_p' || to_char(month, 'YYYYMM'),
+                tbl,
+                month AT TIME ZONE 'UTC',
+                (month + INTERVAL '1 month') AT TIME ZONE 'UTC'
+            );
+            month := month + INTERVAL '1 month';
+        END LOOP;
+    END LOOP;
+END $$;
+
+-- Timestamps are clamped to what TIMESTAMPTZ can hold (year 9999).
+INSERT INTO location_logs (
+    id, device, state, station_id, line_id, segment_id, from_station_id, to_station_id,
+    latitude, longitude, accuracy, speed, timestamp, event_time,
+    battery_level, battery_state, recorded_at
+)
+SELECT
+    id, device, state, station_id, line_id, segment_id, from_station_id, to_station_id,
+    latitude, longitude, accuracy, speed, timestamp,
+    to_timestamp(LEAST(GREATEST(timestamp, 0), 253402300799999) / 1000.0),
+    battery_level, battery_state, recorded_at
+FROM location_logs_legacy;
+
+INSERT INTO log_events (
+    id, device, log_type, log_level, message, timestamp, event_time, recorded_at
+)
+SELECT
+    id, device, log_type, log_level, message, timestamp,
+    to_timestamp(LEAST(GREATEST(timestamp, 0), 253402300799999) / 1000.0),
+    recorded_at
+FROM log_events_legacy;
+
+DROP TABLE location_logs_legacy;
+DROP TABLE log_events_legacy;
+
+CREATE INDEX idx_location_logs_line_event_time ON location_logs (line_id, event_time);
+CREATE INDEX idx_location_logs_device_event_time ON location_logs (device, event_time);
+CREATE INDEX idx_location_logs_segment ON location_logs (segment_id);
+CREATE INDEX idx_log_events_event_time ON log_events (event_time);
+CREATE INDEX idx_log_events_device_event_time ON log_events (device, event_time);
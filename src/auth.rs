@@ -0,0 +1,588 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{errors::ErrorKind, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use subtle::{Choice, ConstantTimeEq};
+
+use crate::domain::SubscriptionFilter;
+
+/// What a credential may do.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Devices: publish events for their own device and watch only that device.
+    #[default]
+    Publisher,
+    /// Dashboards: watch any device, never publish.
+    Viewer,
+    /// The shared token (or auth disabled): unrestricted.
+    Admin,
+}
+
+/// Identity attached to an authenticated request or socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    /// Device this credential is bound to; `None` may act for any device.
+    pub device: Option<String>,
+    pub role: Role,
+    /// Fingerprint of the presented credential, for keying rate limits.
+    pub credential: Option<String>,
+}
+
+impl Principal {
+    /// Principal for the shared token or when auth is disabled.
+    pub fn admin() -> Self {
+        Self {
+            device: None,
+            role: Role::Admin,
+            credential: None,
+        }
+    }
+
+    /// Tag this principal with a fingerprint of the token it was resolved from.
+    pub fn with_credential(mut self, token: &str) -> Self {
+        self.credential = Some(fingerprint(token));
+        self
+    }
+
+    /// Check that this principal may publish an event for `device`.
+    pub fn authorize_publish(&self, device: &str) -> Result<(), String> {
+        if self.role == Role::Viewer {
+            return Err("viewer tokens cannot publish events".to_string());
+        }
+        if self.role == Role::Publisher && self.device.is_none() {
+            return Err("publisher tokens without a device cannot publish".to_string());
+        }
+        if self.device.as_deref().is_some_and(|own| own != device) {
+            return Err(format!(
+                "token is not allowed to publish for device {device}"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check that this principal may read aggregated reports; publishers are write-only.
+    pub fn authorize_read(&self) -> Result<(), String> {
+        if self.role == Role::Publisher {
+            return Err("publisher tokens cannot read reports".to_string());
+        }
+        Ok(())
+    }
+
+    /// Narrow a subscription filter to what this principal may watch.
+    /// Publishers only see their own device; an empty device list is scoped to it.
+    pub fn scope_filter(
+        &self,
+        mut filter: SubscriptionFilter,
+    ) -> Result<SubscriptionFilter, String> {
+        if self.role != Role::Publisher {
+            return Ok(filter);
+        }
+        let Some(own) = self.device.as_deref() else {
+            return Err("publisher tokens without a device cannot subscribe".to_string());
+        };
+        if filter.devices.is_empty() {
+            filter.devices = vec![own.to_string()];
+        } else if filter.devices.iter().any(|d| d != own) {
+            return Err(format!(
+                "publisher token may only subscribe to device {own}"
+            ));
+        }
+        Ok(filter)
+    }
+}
+
+/// A shared (admin) token, optionally valid only until `expires_at`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SharedToken {
+    pub token: String,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl SharedToken {
+    pub fn new(token: impl Into<String>) -> Self {
+        Self {
+            token: token.into(),
+            expires_at: None,
+        }
+    }
+}
+
+/// The set of currently accepted shared tokens. Several can be valid at once
+/// so devices can be moved to a new token gradually; the set can be replaced
+/// at runtime (on SIGHUP).
+#[derive(Debug, Default)]
+pub struct SharedTokens {
+    tokens: RwLock<Vec<SharedToken>>,
+}
+
+impl SharedTokens {
+    pub fn new(tokens: Vec<SharedToken>) -> Self {
+        Self {
+            tokens: RwLock::new(tokens),
+        }
+    }
+
+    pub fn replace(&self, tokens: Vec<SharedToken>) {
+        *self.tokens.write().expect("shared token lock poisoned") = tokens;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens
+            .read()
+            .expect("shared token lock poisoned")
+            .is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens
+            .read()
+            .expect("shared token lock poisoned")
+            .len()
+    }
+
+    /// Whether `token` equals any unexpired shared token. Every token is
+    /// compared in constant time, without short-circuiting on a match.
+    pub fn matches(&self, token: &str, now: DateTime<Utc>) -> bool {
+        let tokens = self.tokens.read().expect("shared token lock poisoned");
+        let mut matched = Choice::from(0);
+        for candidate in tokens.iter() {
+            let live = candidate.expires_at.is_none_or(|exp| now < exp);
+            matched |=
+                candidate.token.as_bytes().ct_eq(token.as_bytes()) & Choice::from(live as u8);
+        }
+        matched.into()
+    }
+}
+
+/// Claims read from a verified JWT. `exp` is required and `nbf` honoured by the
+/// validator; `device` is required for publishers.
+#[derive(Debug, Deserialize)]
+struct JwtClaims {
+    device: Option<String>,
+    #[serde(default)]
+    role: Role,
+}
+
+/// Verifies HS256-signed JWTs issued by our backend.
+pub struct JwtVerifier {
+    key: DecodingKey,
+    validation: Validation,
+}
+
+impl JwtVerifier {
+    pub fn hs256(secret: &[u8]) -> Self {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.validate_nbf = true;
+        validation.validate_aud = false;
+        validation.leeway = 30;
+        Self {
+            key: DecodingKey::from_secret(secret),
+            validation,
+        }
+    }
+
+    /// Compact JWS has three dot-separated segments; opaque tokens never contain dots.
+    pub fn looks_like_jwt(token: &str) -> bool {
+        token.split('.').count() == 3
+    }
+
+    pub fn verify(&self, token: &str) -> Result<Principal, jsonwebtoken::errors::Error> {
+        let data = jsonwebtoken::decode::<JwtClaims>(token, &self.key, &self.validation)?;
+        if data.claims.role == Role::Publisher && data.claims.device.is_none() {
+            return Err(ErrorKind::MissingRequiredClaim("device".to_string()).into());
+        }
+        Ok(Principal {
+            device: data.claims.device,
+            role: data.claims.role,
+            credential: None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TokenEntry {
+    token: String,
+    device: Option<String>,
+    #[serde(default)]
+    role: Role,
+    #[serde(default)]
+    revoked: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RegistryFile {
+    #[serde(default)]
+    tokens: Vec<TokenEntry>,
+}
+
+#[derive(Default)]
+struct Loaded {
+    entries: Vec<TokenEntry>,
+    // (mtime, length) of the file the entries were read from.
+    stamp: Option<(SystemTime, u64)>,
+}
+
+/// Per-device API tokens read from a TOML file:
+///
+/// ```toml
+/// [[tokens]]
+/// token = "..."
+/// device = "device-001"
+/// role = "publisher" # default; or "viewer" / "admin"
+/// ```
+///
+/// The file is re-read when it changes, so tokens can be added or revoked
+/// (removed, or marked `revoked = true`) without a restart.
+pub struct TokenRegistry {
+    path: PathBuf,
+    loaded: RwLock<Loaded>,
+}
+
+impl TokenRegistry {
+    pub fn load(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let registry = Self {
+            path: path.into(),
+            loaded: RwLock::new(Loaded::default()),
+        };
+        registry.reload()?;
+        Ok(registry)
+    }
+
+    /// Number of active (non-revoked) tokens.
+    pub fn active_count(&self) -> usize {
+        self.loaded
+            .read()
+            .expect("token registry lock poisoned")
+            .entries
+            .iter()
+            .filter(|e| !e.revoked)
+            .count()
+    }
+
+    /// Resolve a presented token. Every entry is compared in constant time so
+    /// the lookup does not leak which prefix matched.
+    pub fn lookup(&self, token: &str) -> Option<Principal> {
+        let loaded = self.loaded.read().expect("token registry lock poisoned");
+        let mut found = None;
+        for entry in &loaded.entries {
+            let matches: bool = entry.token.as_bytes().ct_eq(token.as_bytes()).into();
+            if matches && !entry.revoked && found.is_none() {
+                found = Some(Principal {
+                    device: entry.device.clone(),
+                    role: entry.role,
+                    credential: None,
+                });
+            }
+        }
+        found
+    }
+
+    /// Re-read the file unconditionally.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let stamp = file_stamp(&self.path)?;
+        let raw = fs::read_to_string(&self.path)
+            .with_context(|| format!("failed to read token registry at {}", self.path.display()))?;
+        let file: RegistryFile = toml::from_str(&raw).with_context(|| {
+            format!("failed to parse token registry at {}", self.path.display())
+        })?;
+        // A publisher without a device would be allowed to publish for every device.
+        if let Some(n) = file
+            .tokens
+            .iter()
+            .position(|e| e.role == Role::Publisher && e.device.is_none())
+        {
+            anyhow::bail!(
+                "token registry at {}: publisher token #{} has no device",
+                self.path.display(),
+                n + 1
+            );
+        }
+
+        let mut loaded = self.loaded.write().expect("token registry lock poisoned");
+        loaded.entries = file.tokens;
+        loaded.stamp = Some(stamp);
+        Ok(())
+    }
+
+    /// Re-read the file if its mtime or size changed. Returns whether it was reloaded.
+    pub fn reload_if_changed(&self) -> anyhow::Result<bool> {
+        let stamp = file_stamp(&self.path)?;
+        let current = self
+            .loaded
+            .read()
+            .expect("token registry lock poisoned")
+            .stamp;
+        if current == Some(stamp) {
+            return Ok(false);
+        }
+        self.reload()?;
+        Ok(true)
+    }
+
+    /// Poll the file for changes. A registry that fails to parse keeps the
+    /// previously loaded tokens.
+    pub fn spawn_watcher(self: Arc<Self>, every: Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(every);
+            loop {
+                ticker.tick().await;
+                match self.reload_if_changed() {
+                    Ok(true) => tracing::info!(
+                        path = %self.path.display(),
+                        tokens = self.active_count(),
+                        "reloaded token registry"
+                    ),
+                    Ok(false) => {}
+                    Err(err) => tracing::warn!(?err, "failed to reload token registry"),
+                }
+            }
+        });
+    }
+}
+
+/// Short SHA-256 fingerprint of a token, safe to keep in memory and logs.
+fn fingerprint(token: &str) -> String {
+    let digest = Sha256::digest(token.as_bytes());
+    hex::encode(&digest[..8])
+}
+
+fn file_stamp(path: &Path) -> anyhow::Result<(SystemTime, u64)> {
+    let meta = fs::metadata(path)
+        .with_context(|| format!("failed to stat token registry at {}", path.display()))?;
+    Ok((meta.modified()?, meta.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn tmp_path() -> PathBuf {
+        let mut p = std::env::temp_dir();
+        p.push(format!("token_registry_{}.toml", Uuid::new_v4()));
+        p
+    }
+
+    #[test]
+    fn lookup_resolves_device_and_skips_revoked() {
+        let path = tmp_path();
+        fs::write(
+            &path,
+            r#"
+[[tokens]]
+token = "tok-a"
+device = "device-a"
+
+[[tokens]]
+token = "tok-b"
+device = "device-b"
+revoked = true
+
+[[tokens]]
+token = "tok-v"
+role = "viewer"
+"#,
+        )
+        .unwrap();
+
+        let registry = TokenRegistry::load(&path).unwrap();
+        assert_eq!(
+            registry.lookup("tok-a"),
+            Some(Principal {
+                device: Some("device-a".into()),
+                role: Role::Publisher,
+                credential: None,
+            })
+        );
+        assert_eq!(registry.lookup("tok-v").unwrap().role, Role::Viewer);
+        assert_eq!(registry.lookup("tok-b"), None);
+        assert_eq!(registry.lookup("unknown"), None);
+        assert_eq!(registry.active_count(), 2);
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn reload_if_changed_picks_up_revocation() {
+        let path = tmp_path();
+        fs::write(
+            &path,
+            "[[tokens]]\ntoken = \"tok-a\"\ndevice = \"device-a\"\n",
+        )
+        .unwrap();
+        let registry = TokenRegistry::load(&path).unwrap();
+        assert!(!registry.reload_if_changed().unwrap());
+
+        fs::write(&path, "tokens = []\n").unwrap();
+        assert!(registry.reload_if_changed().unwrap());
+        assert_eq!(registry.lookup("tok-a"), None);
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn registry_rejects_publisher_without_device() {
+        let path = tmp_path();
+        fs::write(&path, "[[tokens]]\ntoken = \"tok-a\"\n").unwrap();
+        let err = TokenRegistry::load(&path).err().unwrap();
+        assert!(err.to_string().contains("has no device"), "{err}");
+
+        // Viewers are not bound to a device.
+        fs::write(&path, "[[tokens]]\ntoken = \"tok-v\"\nrole = \"viewer\"\n").unwrap();
+        assert!(TokenRegistry::load(&path).is_ok());
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn shared_tokens_honour_expiry() {
+        let now = Utc::now();
+        let tokens = SharedTokens::new(vec![
+            SharedToken::new("current"),
+            SharedToken {
+                token: "old".into(),
+                expires_at: Some(now + chrono::Duration::hours(1)),
+            },
+            SharedToken {
+                token: "expired".into(),
+                expires_at: Some(now - chrono::Duration::seconds(1)),
+            },
+        ]);
+
+        assert!(tokens.matches("current", now));
+        assert!(tokens.matches("old", now));
+        assert!(!tokens.matches("old", now + chrono::Duration::hours(2)));
+        assert!(!tokens.matches("expired", now));
+        assert!(!tokens.matches("unknown", now));
+
+        tokens.replace(vec![SharedToken::new("next")]);
+        assert!(!tokens.matches("current", now));
+        assert!(tokens.matches("next", now));
+    }
+
+    fn sign(claims: serde_json::Value, secret: &[u8]) -> String {
+        jsonwebtoken::encode(
+            &jsonwebtoken::Header::new(Algorithm::HS256),
+            &claims,
+            &jsonwebtoken::EncodingKey::from_secret(secret),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn jwt_claims_become_principal() {
+        let verifier = JwtVerifier::hs256(b"jwt-secret");
+        let exp = Utc::now().timestamp() + 300;
+        let token = sign(
+            serde_json::json!({ "device": "device-a", "role": "publisher", "exp": exp }),
+            b"jwt-secret",
+        );
+
+        assert!(JwtVerifier::looks_like_jwt(&token));
+        assert_eq!(verifier.verify(&token).unwrap(), publisher("device-a"));
+
+        let viewer = sign(
+            serde_json::json!({ "role": "viewer", "exp": exp }),
+            b"jwt-secret",
+        );
+        assert_eq!(verifier.verify(&viewer).unwrap().role, Role::Viewer);
+    }
+
+    #[test]
+    fn jwt_rejects_expired_premature_and_forged_tokens() {
+        let verifier = JwtVerifier::hs256(b"jwt-secret");
+        let now = Utc::now().timestamp();
+
+        let expired = sign(serde_json::json!({ "exp": now - 3600 }), b"jwt-secret");
+        assert!(verifier.verify(&expired).is_err());
+
+        let not_yet = sign(
+            serde_json::json!({ "exp": now + 7200, "nbf": now + 3600 }),
+            b"jwt-secret",
+        );
+        assert!(verifier.verify(&not_yet).is_err());
+
+        let forged = sign(serde_json::json!({ "exp": now + 300 }), b"other-secret");
+        assert!(verifier.verify(&forged).is_err());
+
+        let no_exp = sign(serde_json::json!({ "device": "device-a" }), b"jwt-secret");
+        assert!(verifier.verify(&no_exp).is_err());
+    }
+
+    #[test]
+    fn jwt_publisher_requires_device_claim() {
+        let verifier = JwtVerifier::hs256(b"jwt-secret");
+        let exp = Utc::now().timestamp() + 300;
+
+        for claims in [
+            serde_json::json!({ "exp": exp }),
+            serde_json::json!({ "role": "publisher", "exp": exp }),
+        ] {
+            let err = verifier.verify(&sign(claims, b"jwt-secret")).unwrap_err();
+            assert_eq!(
+                err.kind(),
+                &ErrorKind::MissingRequiredClaim("device".to_string())
+            );
+        }
+    }
+
+    fn publisher(device: &str) -> Principal {
+        Principal {
+            device: Some(device.into()),
+            role: Role::Publisher,
+            credential: None,
+        }
+    }
+
+    #[test]
+    fn publish_is_limited_by_role_and_device() {
+        assert!(Principal::admin().authorize_publish("anything").is_ok());
+        assert!(publisher("device-a").authorize_publish("device-a").is_ok());
+        assert!(publisher("device-a").authorize_publish("device-b").is_err());
+
+        let viewer = Principal {
+            device: None,
+            role: Role::Viewer,
+            credential: None,
+        };
+        assert_eq!(
+            viewer.authorize_publish("device-a").unwrap_err(),
+            "viewer tokens cannot publish events"
+        );
+
+        let unbound = Principal {
+            device: None,
+            role: Role::Publisher,
+            credential: None,
+        };
+        assert!(unbound.authorize_publish("device-a").is_err());
+    }
+
+    #[test]
+    fn publisher_subscriptions_are_scoped_to_own_device() {
+        let scoped = publisher("device-a")
+            .scope_filter(SubscriptionFilter::default())
+            .unwrap();
+        assert_eq!(scoped.devices, vec!["device-a".to_string()]);
+
+        let foreign = SubscriptionFilter {
+            devices: vec!["device-b".into()],
+            ..Default::default()
+        };
+        assert!(publisher("device-a").scope_filter(foreign.clone()).is_err());
+        assert_eq!(
+            Principal::admin().scope_filter(foreign.clone()),
+            Ok(foreign)
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use std::time::Duration;
+
+use axum::http::{
+    header::{AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER},
+    HeaderName, HeaderValue, Method,
+};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::signing::{NONCE_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+
+/// Browser origins allowed to call the HTTP API and open WebSockets.
+///
+/// Empty (the default) sends no CORS headers and lets WebSockets connect
+/// from any page; `*` allows every origin. Requests without an `Origin`
+/// header (devices, curl) are never affected.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AllowedOrigins {
+    any: bool,
+    origins: Vec<String>,
+}
+
+impl AllowedOrigins {
+    pub fn parse<S: AsRef<str>>(entries: &[S]) -> anyhow::Result<Self> {
+        let mut allowed = Self::default();
+        for entry in entries {
+            let entry = entry.as_ref().trim();
+            if entry == "*" {
+                allowed.any = true;
+                continue;
+            }
+            // Browsers send `scheme://host[:port]` with no path or trailing slash.
+            let origin = entry.trim_end_matches('/').to_ascii_lowercase();
+            let valid = origin.split_once("://").is_some_and(|(scheme, host)| {
+                !scheme.is_empty() && !host.is_empty() && !host.contains('/')
+            });
+            if !valid || HeaderValue::from_str(&origin).is_err() {
+                anyhow::bail!(
+                    "invalid allowed origin {entry:?}; expected e.g. https://dashboard.example.com"
+                );
+            }
+            allowed.origins.push(origin);
+        }
+        Ok(allowed)
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.any || !self.origins.is_empty()
+    }
+
+    /// Whether a browser page at `origin` may connect. Anything goes when unconfigured.
+    pub fn allows(&self, origin: &HeaderValue) -> bool {
+        if self.any || !self.is_configured() {
+            return true;
+        }
+        origin.to_str().is_ok_and(|origin| {
+            self.origins
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(origin))
+        })
+    }
+
+    /// CORS for the REST, SSE and GraphQL routes; `None` when unconfigured.
+    pub fn cors_layer(&self) -> Option<CorsLayer> {
+        if !self.is_configured() {
+            return None;
+        }
+        let allow_origin = if self.any {
+            AllowOrigin::any()
+        } else {
+            let origins = self.clone();
+            AllowOrigin::predicate(move |origin, _| origins.allows(origin))
+        };
+        Some(
+            CorsLayer::new()
+                .allow_origin(allow_origin)
+                .allow_methods([Method::GET, Method::POST])
+                .allow_headers([
+                    AUTHORIZATION,
+                    CONTENT_TYPE,
+                    HeaderName::from_static("last-event-id"),
+                    HeaderName::from_static(TIMESTAMP_HEADER),
+                    HeaderName::from_static(NONCE_HEADER),
+                    HeaderName::from_static(SIGNATURE_HEADER),
+                ])
+                .expose_headers([RETRY_AFTER])
+                .max_age(Duration::from_secs(3600)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn origin(raw: &'static str) -> HeaderValue {
+        HeaderValue::from_static(raw)
+    }
+
+    #[test]
+    fn parse_normalizes_and_rejects_malformed_entries() {
+        let allowed =
+            AllowedOrigins::parse(&["https://Dashboard.example.com/", "http://localhost:3000"])
+                .unwrap();
+        assert_eq!(
+            allowed.origins,
+            vec!["https://dashboard.example.com", "http://localhost:3000"]
+        );
+
+        assert!(AllowedOrigins::parse(&["dashboard.example.com"]).is_err());
+        assert!(AllowedOrigins::parse(&["https://example.com/app"]).is_err());
+    }
+
+    #[test]
+    fn allows_listed_origins_only_when_configured() {
+        let unconfigured = AllowedOrigins::default();
+        assert!(unconfigured.allows(&origin("https://evil.example")));
+        assert!(unconfigured.cors_layer().is_none());
+
+        let allowed = AllowedOrigins::parse(&["https://dashboard.example.com"]).unwrap();
+        assert!(allowed.allows(&origin("https://dashboard.example.com")));
+        assert!(allowed.allows(&origin("https://DASHBOARD.example.com")));
+        assert!(!allowed.allows(&origin("https://evil.example")));
+        assert!(!allowed.allows(&origin("http://dashboard.example.com")));
+
+        let any = AllowedOrigins::parse(&["*"]).unwrap();
+        assert!(any.allows(&origin("https://evil.example")));
+    }
+}
@@ -30,7 +30,7 @@ impl MovementState {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(rename_all = "snake_case")]
 pub enum LogLevel {
     Debug,
@@ -89,7 +89,111 @@ pub enum IncomingMessage {
     Subscribe {
         #[serde(default)]
         device: Option<String>,
+        #[serde(flatten)]
+        filter: SubscriptionFilter,
+        /// Resume after this sequence number instead of replaying the whole buffer.
+        #[serde(default)]
+        since_seq: Option<u64>,
+        /// Epoch `since_seq` belongs to; a different epoch means the server restarted.
+        #[serde(default)]
+        epoch: Option<u64>,
+        /// Backfill persisted events at or after this timestamp (ms) before going live.
+        #[serde(default)]
+        since_timestamp: Option<u64>,
     },
+    /// Replace the filter of an active subscription without replaying the buffer.
+    UpdateSubscription {
+        #[serde(flatten)]
+        filter: SubscriptionFilter,
+    },
+    Unsubscribe,
+    /// Location ingestion over the socket; same payload as `POST /api/location`.
+    LocationUpdate(LocationUpdateRequest),
+    /// Log ingestion over the socket; same payload as `POST /api/log`.
+    Log(LogRequest),
+}
+
+/// Kinds of events fanned out by the telemetry hub.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    LocationUpdate,
+    Log,
+}
+
+/// Subscriber-side filter. Empty lists mean "no restriction".
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct SubscriptionFilter {
+    pub devices: Vec<String>,
+    pub line_ids: Vec<i32>,
+    pub types: Vec<EventKind>,
+    pub min_log_level: Option<LogLevel>,
+}
+
+impl SubscriptionFilter {
+    pub fn matches(&self, meta: &EventMeta) -> bool {
+        if !self.types.is_empty() && !meta.kind.is_some_and(|k| self.types.contains(&k)) {
+            return false;
+        }
+
+        if !self.devices.is_empty()
+            && !meta
+                .device
+                .as_ref()
+                .is_some_and(|d| self.devices.iter().any(|f| f == d))
+        {
+            return false;
+        }
+
+        // Logs carry no line_id, so the line filter only narrows location updates.
+        if let Some(line_id) = meta.line_id {
+            if !self.line_ids.is_empty() && !self.line_ids.contains(&line_id) {
+                return false;
+            }
+        }
+
+        if let (Some(min), Some(level)) = (self.min_log_level.as_ref(), meta.level.as_ref()) {
+            if level < min {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Routing metadata extracted from an outgoing message for filter evaluation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventMeta {
+    pub kind: Option<EventKind>,
+    pub device: Option<String>,
+    pub line_id: Option<i32>,
+    pub level: Option<LogLevel>,
+}
+
+impl From<&OutgoingMessage> for EventMeta {
+    fn from(msg: &OutgoingMessage) -> Self {
+        match msg {
+            OutgoingMessage::LocationUpdate(loc) => EventMeta {
+                kind: Some(EventKind::LocationUpdate),
+                device: Some(loc.device.clone()),
+                line_id: Some(loc.line_id),
+                level: None,
+            },
+            OutgoingMessage::Log(log) => EventMeta {
+                kind: Some(EventKind::Log),
+                device: Some(log.device.clone()),
+                line_id: None,
+                level: Some(log.log.level.clone()),
+            },
+            OutgoingMessage::SubscriptionAck(_)
+            | OutgoingMessage::Ack(_)
+            | OutgoingMessage::Gap(_)
+            | OutgoingMessage::HistoryComplete(_)
+            | OutgoingMessage::Error(_) => EventMeta::default(),
+        }
+    }
 }
 
 /// REST API用の位置情報リクエスト
@@ -127,10 +231,107 @@ pub struct LogRequest {
 pub enum OutgoingMessage {
     LocationUpdate(OutgoingLocation),
     Log(OutgoingLog),
+    SubscriptionAck(OutgoingSubscriptionAck),
+    Ack(OutgoingAck),
+    Gap(OutgoingGap),
+    HistoryComplete(OutgoingHistoryComplete),
     Error(OutgoingError),
 }
 
+/// Marks the end of a database backfill; live events follow.
 #[derive(Debug, Clone, Serialize)]
+pub struct OutgoingHistoryComplete {
+    pub count: usize,
+    /// True when the backfill hit the server-side row limit.
+    pub truncated: bool,
+}
+
+impl OutgoingMessage {
+    /// Id of the telemetry event carried by this message, if any.
+    pub fn event_id(&self) -> Option<&str> {
+        match self {
+            OutgoingMessage::LocationUpdate(loc) => Some(&loc.id),
+            OutgoingMessage::Log(log) => Some(&log.id),
+            _ => None,
+        }
+    }
+}
+
+/// Broadcast envelope: prepends the hub-assigned `seq` and the hub's `epoch` to
+/// an already serialized message object, so the message itself can be
+/// serialized outside the hub's lock.
+pub fn sequenced_json(seq: u64, epoch: u64, message_json: &str) -> String {
+    match message_json.strip_prefix('{') {
+        Some(rest) if rest.trim_start().starts_with('}') => {
+            format!("{{\"seq\":{seq},\"epoch\":{epoch}}}")
+        }
+        Some(rest) => format!("{{\"seq\":{seq},\"epoch\":{epoch},{rest}"),
+        None => message_json.to_string(),
+    }
+}
+
+/// Sequence number of a frame built by [`sequenced_json`]; `None` for unsequenced frames.
+pub fn sequenced_seq(frame: &str) -> Option<u64> {
+    frame
+        .strip_prefix("{\"seq\":")?
+        .split([',', '}'])
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Tells a resuming subscriber that `from_seq..=to_seq` cannot be replayed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OutgoingGap {
+    /// Unset on a `reset` when nothing broadcast since the restart is missing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_seq: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to_seq: Option<u64>,
+    pub reason: GapReason,
+    /// Number of frames dropped for this subscriber; set for `slow_consumer` gaps.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dropped: Option<u64>,
+    /// The server's current epoch; set for `reset` gaps.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub epoch: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GapReason {
+    /// The events were pushed out of the ring buffer.
+    Evicted,
+    /// The server restarted and its epoch changed; resume from the buffer start.
+    Reset,
+    /// Frames were dropped because the subscriber could not keep up.
+    SlowConsumer,
+}
+
+/// Acknowledges an event ingested over the WebSocket.
+#[derive(Debug, Clone, Serialize)]
+pub struct OutgoingAck {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OutgoingSubscriptionAck {
+    pub action: SubscriptionAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<SubscriptionFilter>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionAction {
+    Subscribed,
+    Updated,
+    Unsubscribed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OutgoingLocation {
     pub id: String,
     pub device: String,
@@ -146,7 +347,7 @@ pub struct OutgoingLocation {
     pub battery_state: Option<BatteryState>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OutgoingCoords {
     pub latitude: f64,
     pub longitude: f64,
@@ -154,7 +355,7 @@ pub struct OutgoingCoords {
     pub speed: Option<f64>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OutgoingLog {
     pub id: String,
     pub device: String,
@@ -164,6 +365,9 @@ pub struct OutgoingLog {
 
 #[derive(Debug, Clone, Serialize)]
 pub struct OutgoingError {
+    /// Event id the error refers to, for rejected ingestion frames.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
     pub error: ErrorBody,
 }
 
@@ -178,6 +382,10 @@ pub struct ErrorBody {
 pub enum ErrorType {
     WebsocketMessageError,
     JsonParseError,
+    InvalidPayload,
+    Forbidden,
+    RateLimited,
+    AlreadySubscribed,
 }
 
 #[cfg(test)]
@@ -189,12 +397,116 @@ mod tests {
         let json = r#"{"type":"subscribe","device":"dev"}"#;
         let v: IncomingMessage = serde_json::from_str(json).unwrap();
         match v {
-            IncomingMessage::Subscribe { device } => {
+            IncomingMessage::Subscribe {
+                device,
+                filter,
+                since_seq,
+                epoch,
+                since_timestamp,
+            } => {
                 assert_eq!(device.as_deref(), Some("dev"));
+                assert_eq!(filter, SubscriptionFilter::default());
+                assert!(since_seq.is_none());
+                assert!(epoch.is_none());
+                assert!(since_timestamp.is_none());
             }
+            other => panic!("unexpected message: {other:?}"),
         }
     }
 
+    #[test]
+    fn incoming_subscribe_deserializes_filters() {
+        let json = r#"{
+            "type":"subscribe",
+            "devices":["a","b"],
+            "line_ids":[7],
+            "types":["log"],
+            "min_log_level":"warn",
+            "since_seq":42,
+            "epoch":1700000000000
+        }"#;
+        let v: IncomingMessage = serde_json::from_str(json).unwrap();
+        let IncomingMessage::Subscribe {
+            device,
+            filter,
+            since_seq,
+            epoch,
+            ..
+        } = v
+        else {
+            panic!("expected subscribe");
+        };
+        assert!(device.is_none());
+        assert_eq!(since_seq, Some(42));
+        assert_eq!(epoch, Some(1_700_000_000_000));
+        assert_eq!(filter.devices, vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(filter.line_ids, vec![7]);
+        assert_eq!(filter.types, vec![EventKind::Log]);
+        assert_eq!(filter.min_log_level, Some(LogLevel::Warn));
+    }
+
+    #[test]
+    fn incoming_update_and_unsubscribe_deserialize() {
+        let json = r#"{"type":"update_subscription","line_ids":[3]}"#;
+        let v: IncomingMessage = serde_json::from_str(json).unwrap();
+        let IncomingMessage::UpdateSubscription { filter } = v else {
+            panic!("expected update_subscription");
+        };
+        assert_eq!(filter.line_ids, vec![3]);
+
+        let v: IncomingMessage = serde_json::from_str(r#"{"type":"unsubscribe"}"#).unwrap();
+        assert!(matches!(v, IncomingMessage::Unsubscribe));
+    }
+
+    #[test]
+    fn incoming_location_update_deserializes() {
+        let json = r#"{
+            "type":"location_update",
+            "id":"evt-1",
+            "device":"dev",
+            "state":"arrived",
+            "stationId":42,
+            "lineId":7,
+            "coords":{"latitude":1.0,"longitude":2.0},
+            "timestamp":123
+        }"#;
+        let v: IncomingMessage = serde_json::from_str(json).unwrap();
+        let IncomingMessage::LocationUpdate(req) = v else {
+            panic!("expected location_update");
+        };
+        assert_eq!(req.id.as_deref(), Some("evt-1"));
+        assert_eq!(req.station_id, Some(42));
+    }
+
+    #[test]
+    fn filter_matches_device_line_and_level() {
+        let filter = SubscriptionFilter {
+            devices: vec!["dev".into()],
+            line_ids: vec![7],
+            types: vec![],
+            min_log_level: Some(LogLevel::Warn),
+        };
+
+        let loc = |device: &str, line_id| EventMeta {
+            kind: Some(EventKind::LocationUpdate),
+            device: Some(device.into()),
+            line_id: Some(line_id),
+            level: None,
+        };
+        let log = |level| EventMeta {
+            kind: Some(EventKind::Log),
+            device: Some("dev".into()),
+            line_id: None,
+            level: Some(level),
+        };
+
+        assert!(filter.matches(&loc("dev", 7)));
+        assert!(!filter.matches(&loc("dev", 8)));
+        assert!(!filter.matches(&loc("other", 7)));
+        assert!(filter.matches(&log(LogLevel::Error)));
+        assert!(!filter.matches(&log(LogLevel::Info)));
+    }
+
     #[test]
     fn location_update_request_deserializes() {
         let json = r#"{
@@ -238,4 +550,27 @@ mod tests {
         assert_eq!(json["device"], "dev");
         assert_eq!(json["coords"]["speed"], 3.0); // Some(3.0) serializes as 3.0
     }
+
+    #[test]
+    fn sequenced_json_prepends_seq_and_epoch() {
+        let msg = OutgoingMessage::Log(OutgoingLog {
+            id: "id1".into(),
+            device: "dev".into(),
+            timestamp: 42,
+            log: LogBody {
+                r#type: LogType::App,
+                level: LogLevel::Info,
+                message: "hi".into(),
+            },
+        });
+
+        let raw = sequenced_json(9, 5, &serde_json::to_string(&msg).unwrap());
+        let json: serde_json::Value = serde_json::from_str(&raw).unwrap();
+        assert_eq!(json["seq"], 9);
+        assert_eq!(json["epoch"], 5);
+        assert_eq!(sequenced_seq(&raw), Some(9));
+        assert_eq!(sequenced_seq(r#"{"type":"gap"}"#), None);
+        assert_eq!(json["type"], "log");
+        assert_eq!(json["log"]["message"], "hi");
+    }
 }
@@ -63,9 +63,28 @@ pub struct LineAccuracyReport {
     pub buckets: Vec<LineAccuracyBucket>,
 }
 
-pub fn build_schema(storage: Storage) -> AppSchema {
+/// Per-request limits on query shape, enforced before execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryLimits {
+    pub max_depth: usize,
+    pub max_complexity: usize,
+}
+
+impl Default for QueryLimits {
+    fn default() -> Self {
+        // Leaves room for the playground's introspection query.
+        Self {
+            max_depth: 15,
+            max_complexity: 1000,
+        }
+    }
+}
+
+pub fn build_schema(storage: Storage, limits: QueryLimits) -> AppSchema {
     Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
         .data(storage)
+        .limit_depth(limits.max_depth)
+        .limit_complexity(limits.max_complexity)
         .finish()
 }
 
@@ -196,4 +215,33 @@ mod tests {
         let to = from + ChronoDuration::seconds(61);
         assert_eq!(estimate_bucket_count(from, to, 60), 2);
     }
+
+    #[tokio::test]
+    async fn queries_beyond_depth_limit_are_rejected() {
+        let schema = build_schema(
+            Storage::default(),
+            QueryLimits {
+                max_depth: 2,
+                max_complexity: 1000,
+            },
+        );
+
+        let res = schema
+            .execute(
+                r#"{ accuracyByLine(lineId: "1", from: "2024-12-01T00:00:00Z", to: "2024-12-01T01:00:00Z", bucketSize: MINUTE) { buckets { sampleCount } } }"#,
+            )
+            .await;
+
+        assert_eq!(res.errors.len(), 1);
+        assert!(res.errors[0].message.contains("nested too deep"));
+    }
+
+    #[tokio::test]
+    async fn default_limits_allow_introspection() {
+        let schema = build_schema(Storage::default(), QueryLimits::default());
+        let res = schema
+            .execute("{ __schema { types { name fields { name type { name kind ofType { name kind ofType { name kind ofType { name } } } } } } } }")
+            .await;
+        assert!(res.errors.is_empty(), "{:?}", res.errors);
+    }
 }
@@ -1,24 +1,38 @@
+mod auth;
 mod config;
+mod cors;
 mod domain;
 mod graphql;
+mod metrics;
+mod migrate;
+mod partition;
+mod ratelimit;
 mod segment;
 mod server;
+mod signing;
+mod spool;
 mod state;
 mod storage;
+mod tls;
 
 use clap::Parser;
-use config::{Cli, Config};
+use config::{Cli, Command, Config};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     init_tracing();
     let cli = Cli::parse();
+    let command = cli.command.clone();
     let config = Config::from_cli(cli)?;
+    if command == Some(Command::Migrate) {
+        return storage::migrate(&config).await;
+    }
     tracing::info!(
         host = %config.host,
         port = config.port,
         db = %config.database_url.as_deref().unwrap_or("<none>"),
-        ws_auth_configured = config.ws_auth_token.is_some(),
+        ws_auth_configured = !config.shared_tokens().is_empty(),
+        token_registry = ?config.token_registry,
         ws_auth_required = config.ws_auth_required,
         "starting thq-server"
     );
@@ -0,0 +1,196 @@
+use std::sync::LazyLock;
+
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// Process-wide Prometheus metrics, served on `GET /metrics`.
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+pub struct Metrics {
+    registry: Registry,
+    /// Accepted location updates, by `device` and `line_id`.
+    pub locations_ingested: IntCounterVec,
+    /// Accepted log events, by `device`.
+    pub logs_ingested: IntCounterVec,
+    /// Events rejected by validation, by `kind` (location/log) and `reason`.
+    pub validation_rejections: IntCounterVec,
+    /// Requests rejected by a rate limit, by `scope` (device/credential).
+    pub rate_limit_rejections: IntCounterVec,
+    /// Frames dropped or coalesced away for slow subscribers.
+    pub broadcast_dropped: IntCounter,
+    /// Subscribers disconnected by the `disconnect` slow-consumer policy.
+    pub slow_consumer_disconnects: IntCounter,
+    pub subscribers: IntGauge,
+    pub ring_buffer_events: IntGauge,
+    pub ring_buffer_capacity: IntGauge,
+    /// Time spent in batched `INSERT`s, by `table`.
+    pub db_insert_seconds: HistogramVec,
+    pub db_insert_failures: IntCounterVec,
+    /// Batches written to the spool after a failed insert, by `table`.
+    pub spooled_batches: IntCounterVec,
+    /// Spooled batches later stored in PostgreSQL, by `table`.
+    pub spool_drained_batches: IntCounterVec,
+    /// Batches lost because the spool was full or unwritable, by `table`.
+    pub spool_dropped_batches: IntCounterVec,
+    /// Spooled batches set aside after failing repeatedly, by `table`.
+    pub spool_quarantined_batches: IntCounterVec,
+    /// Rows PostgreSQL refused for good (invalid data, constraint violations), by `table`.
+    pub db_rejected_rows: IntCounterVec,
+    pub spool_pending_batches: IntGauge,
+    pub spool_bytes: IntGauge,
+    /// Segment inference outcomes: `hit`, `miss`, or `unknown_line` without topology.
+    pub segment_inference: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry =
+            Registry::new_custom(Some("thq".to_string()), None).expect("valid metrics namespace");
+
+        let counter_vec = |name: &str, help: &str, labels: &[&str]| {
+            let metric = IntCounterVec::new(Opts::new(name, help), labels).expect("valid metric");
+            registry
+                .register(Box::new(metric.clone()))
+                .expect("metric registered once");
+            metric
+        };
+        let counter = |name: &str, help: &str| {
+            let metric = IntCounter::new(name, help).expect("valid metric");
+            registry
+                .register(Box::new(metric.clone()))
+                .expect("metric registered once");
+            metric
+        };
+        let gauge = |name: &str, help: &str| {
+            let metric = IntGauge::new(name, help).expect("valid metric");
+            registry
+                .register(Box::new(metric.clone()))
+                .expect("metric registered once");
+            metric
+        };
+
+        let db_insert_seconds = HistogramVec::new(
+            HistogramOpts::new("db_insert_seconds", "Duration of database inserts").buckets(vec![
+                0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+            ]),
+            &["table"],
+        )
+        .expect("valid metric");
+        registry
+            .register(Box::new(db_insert_seconds.clone()))
+            .expect("metric registered once");
+
+        Self {
+            locations_ingested: counter_vec(
+                "locations_ingested_total",
+                "Accepted location updates",
+                &["device", "line_id"],
+            ),
+            logs_ingested: counter_vec("logs_ingested_total", "Accepted log events", &["device"]),
+            validation_rejections: counter_vec(
+                "validation_rejections_total",
+                "Events rejected by validation",
+                &["kind", "reason"],
+            ),
+            rate_limit_rejections: counter_vec(
+                "rate_limit_rejections_total",
+                "Ingestion requests rejected by a rate limit",
+                &["scope"],
+            ),
+            broadcast_dropped: counter(
+                "broadcast_dropped_frames_total",
+                "Frames dropped or coalesced for slow subscribers",
+            ),
+            slow_consumer_disconnects: counter(
+                "slow_consumer_disconnects_total",
+                "Subscribers disconnected for falling behind",
+            ),
+            subscribers: gauge("subscribers", "Connected live subscribers"),
+            ring_buffer_events: gauge("ring_buffer_events", "Events held in the ring buffer"),
+            ring_buffer_capacity: gauge("ring_buffer_capacity", "Ring buffer capacity"),
+            db_insert_seconds,
+            db_insert_failures: counter_vec(
+                "db_insert_failures_total",
+                "Failed database inserts",
+                &["table"],
+            ),
+            spooled_batches: counter_vec(
+                "spooled_batches_total",
+                "Batches spooled to disk after a failed insert",
+                &["table"],
+            ),
+            spool_drained_batches: counter_vec(
+                "spool_drained_batches_total",
+                "Spooled batches stored after the database recovered",
+                &["table"],
+            ),
+            spool_dropped_batches: counter_vec(
+                "spool_dropped_batches_total",
+                "Batches lost because the spool was full or unwritable",
+                &["table"],
+            ),
+            spool_quarantined_batches: counter_vec(
+                "spool_quarantined_batches_total",
+                "Spooled batches moved to the rejected file after failing repeatedly",
+                &["table"],
+            ),
+            db_rejected_rows: counter_vec(
+                "db_rejected_rows_total",
+                "Rows refused by the database because of their data",
+                &["table"],
+            ),
+            spool_pending_batches: gauge("spool_pending_batches", "Batches waiting in the spool"),
+            spool_bytes: gauge("spool_bytes", "Size of the spool file"),
+            segment_inference: counter_vec(
+                "segment_inference_total",
+                "Segment inference outcomes for location updates",
+                &["result"],
+            ),
+            registry,
+        }
+    }
+
+    /// Count a validation failure and hand back its message.
+    pub fn reject(&self, kind: &str, reason: &str, message: String) -> String {
+        self.validation_rejections
+            .with_label_values(&[kind, reason])
+            .inc();
+        message
+    }
+
+    /// Render every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut buf = Vec::new();
+        if let Err(err) = TextEncoder::new().encode(&self.registry.gather(), &mut buf) {
+            tracing::error!(?err, "failed to encode metrics");
+        }
+        String::from_utf8(buf).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_includes_namespaced_metrics() {
+        let m = metrics();
+        m.logs_ingested
+            .with_label_values(&["metrics-render-device"])
+            .inc();
+        let message = m.reject("log", "empty_message", "empty".to_string());
+        assert_eq!(message, "empty");
+
+        let text = m.render();
+        assert!(text.contains("thq_logs_ingested_total{device=\"metrics-render-device\"} 1"));
+        assert!(
+            text.contains("thq_validation_rejections_total{kind=\"log\",reason=\"empty_message\"}")
+        );
+    }
+}
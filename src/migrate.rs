@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use sqlx::{
+    migrate::{Migration, Migrator},
+    Connection, Executor, PgPool,
+};
+
+/// SQL files in `migrations/`, embedded at compile time and applied in version order.
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Arbitrary key for the advisory lock that serializes concurrent migrators.
+const MIGRATION_LOCK_KEY: i64 = 0x7468_715f_6d69_6772; // "thq_migr"
+
+const CREATE_SCHEMA_MIGRATIONS: &str = r#"
+CREATE TABLE IF NOT EXISTS schema_migrations (
+    version BIGINT PRIMARY KEY,
+    description TEXT NOT NULL,
+    checksum BYTEA NOT NULL,
+    applied_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+"#;
+
+/// Apply every embedded migration missing from `schema_migrations`, each in
+/// its own transaction. Returns the versions applied by this call.
+pub async fn run(pool: &PgPool) -> anyhow::Result<Vec<i64>> {
+    // Session-level advisory locks belong to a connection, so hold one for the whole run.
+    let mut conn = pool.acquire().await?;
+    sqlx::query("SELECT pg_advisory_lock($1)")
+        .bind(MIGRATION_LOCK_KEY)
+        .execute(&mut *conn)
+        .await
+        .context("failed to take the migration lock")?;
+
+    let result = async {
+        conn.execute(CREATE_SCHEMA_MIGRATIONS)
+            .await
+            .context("failed to create schema_migrations")?;
+        let applied = applied_versions(&mut conn).await?;
+
+        let mut ran = Vec::new();
+        for migration in pending(MIGRATOR.iter(), &applied)? {
+            let mut tx = conn.begin().await?;
+            tx.execute(&*migration.sql).await.with_context(|| {
+                format!(
+                    "migration {} ({}) failed",
+                    migration.version, migration.description
+                )
+            })?;
+            sqlx::query(
+                "INSERT INTO schema_migrations (version, description, checksum) VALUES ($1, $2, $3)",
+            )
+            .bind(migration.version)
+            .bind(&*migration.description)
+            .bind(&*migration.checksum)
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+            tracing::info!(
+                version = migration.version,
+                description = %migration.description,
+                "applied migration"
+            );
+            ran.push(migration.version);
+        }
+        anyhow::Ok(ran)
+    }
+    .await;
+
+    let unlocked = sqlx::query("SELECT pg_advisory_unlock($1)")
+        .bind(MIGRATION_LOCK_KEY)
+        .execute(&mut *conn)
+        .await;
+    if let Err(err) = unlocked {
+        // Dropping the connection from the pool releases the lock with its session.
+        tracing::warn!(?err, "failed to release the migration lock");
+        conn.detach();
+    }
+    result
+}
+
+/// Fail unless every embedded migration has been applied, for servers started
+/// with `--no-migrate` against a schema managed elsewhere.
+pub async fn ensure_current(pool: &PgPool) -> anyhow::Result<()> {
+    let mut conn = pool.acquire().await?;
+    let exists: bool = sqlx::query_scalar("SELECT to_regclass('schema_migrations') IS NOT NULL")
+        .fetch_one(&mut *conn)
+        .await?;
+    let applied = if exists {
+        applied_versions(&mut conn).await?
+    } else {
+        HashMap::new()
+    };
+    let missing: Vec<i64> = pending(MIGRATOR.iter(), &applied)?
+        .map(|m| m.version)
+        .collect();
+    if !missing.is_empty() {
+        anyhow::bail!(
+            "database schema is missing migrations {missing:?}; run `thq-server migrate` against the primary"
+        );
+    }
+    Ok(())
+}
+
+async fn applied_versions(conn: &mut sqlx::PgConnection) -> anyhow::Result<HashMap<i64, Vec<u8>>> {
+    let rows: Vec<(i64, Vec<u8>)> =
+        sqlx::query_as("SELECT version, checksum FROM schema_migrations ORDER BY version")
+            .fetch_all(conn)
+            .await
+            .context("failed to read schema_migrations")?;
+    Ok(rows.into_iter().collect())
+}
+
+/// Embedded migrations not yet applied, in order. Errors if an applied
+/// migration's SQL has since been edited; versions applied by a newer build
+/// are tolerated so a rolling deploy can run old and new servers side by side.
+fn pending<'a>(
+    embedded: impl Iterator<Item = &'a Migration>,
+    applied: &HashMap<i64, Vec<u8>>,
+) -> anyhow::Result<impl Iterator<Item = &'a Migration>> {
+    let mut todo = Vec::new();
+    for migration in embedded {
+        match applied.get(&migration.version) {
+            Some(checksum) if checksum.as_slice() != &*migration.checksum => anyhow::bail!(
+                "migration {} ({}) was modified after it was applied; add a new migration instead",
+                migration.version,
+                migration.description
+            ),
+            Some(_) => {}
+            None => todo.push(migration),
+        }
+    }
+    Ok(todo.into_iter())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checksums(migrations: &[&Migration]) -> HashMap<i64, Vec<u8>> {
+        migrations
+            .iter()
+            .map(|m| (m.version, m.checksum.to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn embedded_migrations_are_ordered_and_start_with_the_baseline() {
+        let versions: Vec<i64> = MIGRATOR.iter().map(|m| m.version).collect();
+        assert_eq!(versions.first(), Some(&1));
+        assert!(versions.windows(2).all(|w| w[0] < w[1]), "{versions:?}");
+        assert!(MIGRATOR
+            .iter()
+            .next()
+            .unwrap()
+            .sql
+            .contains("location_logs"));
+    }
+
+    #[test]
+    fn pending_skips_applied_and_rejects_edited_migrations() {
+        let all: Vec<&Migration> = MIGRATOR.iter().collect();
+        let todo: Vec<i64> = pending(MIGRATOR.iter(), &HashMap::new())
+            .unwrap()
+            .map(|m| m.version)
+            .collect();
+        assert_eq!(todo.len(), all.len());
+
+        let mut applied = checksums(&all);
+        assert_eq!(pending(MIGRATOR.iter(), &applied).unwrap().count(), 0);
+
+        // A version this build does not know about (from a newer build) is fine.
+        applied.insert(i64::MAX, vec![0]);
+        assert_eq!(pending(MIGRATOR.iter(), &applied).unwrap().count(), 0);
+
+        applied.insert(all[0].version, vec![0]);
+        let err = pending(MIGRATOR.iter(), &applied).err().unwrap();
+        assert!(err.to_string().contains("was modified"), "{err}");
+    }
+}
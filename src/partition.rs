@@ -0,0 +1,333 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Datelike, Months, TimeZone, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+/// Tables partitioned by `event_time` (see `migrations/0002_partition_by_event_time.sql`).
+const PARTITIONED_TABLES: [&str; 2] = ["location_logs", "log_events"];
+
+/// Periods created ahead of the current one, so midnight never finds a table missing.
+const PERIODS_AHEAD: u32 = 2;
+
+/// Range covered by each partition the server creates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PartitionInterval {
+    Day,
+    #[default]
+    Month,
+}
+
+impl PartitionInterval {
+    /// Start of the period containing `at`, in UTC.
+    fn start_of(self, at: DateTime<Utc>) -> DateTime<Utc> {
+        let day = match self {
+            PartitionInterval::Day => at.day(),
+            PartitionInterval::Month => 1,
+        };
+        Utc.with_ymd_and_hms(at.year(), at.month(), day, 0, 0, 0)
+            .single()
+            .expect("midnight UTC is unambiguous")
+    }
+
+    fn next(self, start: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            PartitionInterval::Day => start + chrono::Duration::days(1),
+            PartitionInterval::Month => start + Months::new(1),
+        }
+    }
+
+    fn suffix(self, start: DateTime<Utc>) -> String {
+        match self {
+            PartitionInterval::Day => start.format("%Y%m%d").to_string(),
+            PartitionInterval::Month => start.format("%Y%m").to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PartitionConfig {
+    pub interval: PartitionInterval,
+    /// Partitions whose whole range is older than this are dropped; `None` keeps everything.
+    pub retention: Option<Duration>,
+}
+
+/// A child partition and its range; `None` for the DEFAULT partition.
+struct Partition {
+    name: String,
+    range: Option<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+/// What one maintenance pass changed.
+#[derive(Debug, Default)]
+pub struct MaintenanceReport {
+    pub created: Vec<String>,
+    pub dropped: Vec<String>,
+    /// Rows moved out of the DEFAULT partitions into newly created ones.
+    pub moved_rows: u64,
+    /// Expired rows deleted from the DEFAULT partitions.
+    pub purged_rows: u64,
+}
+
+/// Periods `[start, end)` that should exist at `now`: the current one and the next few.
+fn upcoming(
+    interval: PartitionInterval,
+    now: DateTime<Utc>,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let mut start = interval.start_of(now);
+    let mut ranges = Vec::new();
+    for _ in 0..=PERIODS_AHEAD {
+        let end = interval.next(start);
+        ranges.push((start, end));
+        start = end;
+    }
+    ranges
+}
+
+fn overlaps(partitions: &[Partition], start: DateTime<Utc>, end: DateTime<Utc>) -> bool {
+    partitions
+        .iter()
+        .filter_map(|p| p.range)
+        .any(|(s, e)| s < end && start < e)
+}
+
+/// Partitions that end at or before `cutoff`; the DEFAULT partition is never expired.
+fn expired(partitions: &[Partition], cutoff: DateTime<Utc>) -> impl Iterator<Item = &str> {
+    partitions
+        .iter()
+        .filter(move |p| p.range.is_some_and(|(_, end)| end <= cutoff))
+        .map(|p| p.name.as_str())
+}
+
+#[derive(sqlx::FromRow)]
+struct PartitionRow {
+    name: String,
+    range_start: Option<DateTime<Utc>>,
+    range_end: Option<DateTime<Utc>>,
+}
+
+async fn partitions(pool: &PgPool, table: &str) -> anyhow::Result<Vec<Partition>> {
+    // Bounds are read back from the catalog, so partitions created by the
+    // migration and by any interval setting are all handled.
+    let rows = sqlx::query_as::<_, PartitionRow>(
+        r#"
+        SELECT
+            c.relname::text AS name,
+            substring(pg_get_expr(c.relpartbound, c.oid) FROM $$FROM \('([^']+)'\)$$)::timestamptz
+                AS range_start,
+            substring(pg_get_expr(c.relpartbound, c.oid) FROM $$TO \('([^']+)'\)$$)::timestamptz
+                AS range_end
+        FROM pg_inherits i
+        JOIN pg_class c ON c.oid = i.inhrelid
+        WHERE i.inhparent = $1::regclass
+        "#,
+    )
+    .bind(table)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| Partition {
+            name: row.name,
+            range: row.range_start.zip(row.range_end),
+        })
+        .collect())
+}
+
+/// Create `name` for `[start, end)` and move the rows the DEFAULT partition
+/// already holds for that range into it. A plain `PARTITION OF` fails when
+/// such rows exist, so the table is created detached, filled, then attached,
+/// all in one transaction. Returns the number of rows moved.
+///
+/// The DEFAULT partition is locked first: a row inserted into it for this
+/// range between the move and the attach (by ingestion, the spool drainer or
+/// another server) would make the attach fail. Those inserts wait for the
+/// commit instead.
+async fn create_partition(
+    pool: &PgPool,
+    table: &str,
+    name: &str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> anyhow::Result<u64> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(&format!(
+        "LOCK TABLE {table}_default IN ACCESS EXCLUSIVE MODE"
+    ))
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(&format!(
+        "CREATE TABLE {name} (LIKE {table} INCLUDING DEFAULTS INCLUDING CONSTRAINTS)"
+    ))
+    .execute(&mut *tx)
+    .await?;
+    let moved = sqlx::query(&format!(
+        "WITH moved AS (DELETE FROM {table}_default WHERE event_time >= $1 AND event_time < $2 RETURNING *) \
+         INSERT INTO {name} SELECT * FROM moved"
+    ))
+    .bind(start)
+    .bind(end)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+    sqlx::query(&format!(
+        "ALTER TABLE {table} ATTACH PARTITION {name} FOR VALUES FROM ('{}') TO ('{}')",
+        start.to_rfc3339(),
+        end.to_rfc3339()
+    ))
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(moved)
+}
+
+/// Create the partitions for the current and upcoming periods, then apply
+/// retention: drop partitions entirely older than the cutoff and delete
+/// expired rows that landed in the DEFAULT partition.
+///
+/// A period that overlaps an existing partition (e.g. after switching from
+/// monthly to daily) is skipped; its rows go to the partition already there.
+/// Rows that landed in the DEFAULT partition before their period's partition
+/// existed are moved into it.
+pub async fn maintain(
+    pool: &PgPool,
+    config: &PartitionConfig,
+    now: DateTime<Utc>,
+) -> anyhow::Result<MaintenanceReport> {
+    let mut report = MaintenanceReport::default();
+    for table in PARTITIONED_TABLES {
+        let existing = partitions(pool, table).await?;
+        for (start, end) in upcoming(config.interval, now) {
+            if overlaps(&existing, start, end) {
+                continue;
+            }
+            let name = format!("{table}_p{}", config.interval.suffix(start));
+            report.moved_rows += create_partition(pool, table, &name, start, end)
+                .await
+                .with_context(|| format!("failed to create partition {name}"))?;
+            report.created.push(name);
+        }
+
+        let Some(retention) = config.retention else {
+            continue;
+        };
+        let cutoff = now - chrono::Duration::from_std(retention)?;
+        for name in expired(&existing, cutoff) {
+            sqlx::query(&format!("DROP TABLE IF EXISTS {name}"))
+                .execute(pool)
+                .await?;
+            report.dropped.push(name.to_string());
+        }
+        let purged = sqlx::query(&format!(
+            "DELETE FROM {table}_default WHERE event_time < $1"
+        ))
+        .bind(cutoff)
+        .execute(pool)
+        .await?;
+        report.purged_rows += purged.rows_affected();
+    }
+    Ok(report)
+}
+
+/// Run [`maintain`] every `every` in the background.
+pub fn spawn_maintenance(pool: PgPool, config: PartitionConfig, every: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(every);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            match maintain(&pool, &config, Utc::now()).await {
+                Ok(report) => log_report(&report),
+                Err(err) => tracing::warn!(?err, "partition maintenance failed"),
+            }
+        }
+    });
+}
+
+pub fn log_report(report: &MaintenanceReport) {
+    if !report.created.is_empty() {
+        tracing::info!(
+            partitions = ?report.created,
+            default_rows = report.moved_rows,
+            "created partitions"
+        );
+    }
+    if !report.dropped.is_empty() || report.purged_rows > 0 {
+        tracing::info!(
+            partitions = ?report.dropped,
+            default_rows = report.purged_rows,
+            "dropped expired data"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(y: i32, m: u32, d: u32, h: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, 0, 0).unwrap()
+    }
+
+    fn partition(name: &str, range: Option<(DateTime<Utc>, DateTime<Utc>)>) -> Partition {
+        Partition {
+            name: name.to_string(),
+            range,
+        }
+    }
+
+    #[test]
+    fn upcoming_covers_the_current_period_and_the_next_ones() {
+        let daily = upcoming(PartitionInterval::Day, at(2026, 12, 31, 23));
+        assert_eq!(
+            daily,
+            vec![
+                (at(2026, 12, 31, 0), at(2027, 1, 1, 0)),
+                (at(2027, 1, 1, 0), at(2027, 1, 2, 0)),
+                (at(2027, 1, 2, 0), at(2027, 1, 3, 0)),
+            ]
+        );
+
+        let monthly = upcoming(PartitionInterval::Month, at(2026, 1, 31, 12));
+        assert_eq!(monthly[0], (at(2026, 1, 1, 0), at(2026, 2, 1, 0)));
+        assert_eq!(monthly[1], (at(2026, 2, 1, 0), at(2026, 3, 1, 0)));
+        assert_eq!(PartitionInterval::Month.suffix(monthly[1].0), "202602");
+        assert_eq!(PartitionInterval::Day.suffix(daily[1].0), "20270101");
+    }
+
+    #[test]
+    fn overlapping_periods_are_detected() {
+        let existing = vec![
+            partition("t_p202610", Some((at(2026, 10, 1, 0), at(2026, 11, 1, 0)))),
+            partition("t_default", None),
+        ];
+        assert!(overlaps(
+            &existing,
+            at(2026, 10, 16, 0),
+            at(2026, 10, 17, 0)
+        ));
+        assert!(!overlaps(&existing, at(2026, 11, 1, 0), at(2026, 11, 2, 0)));
+        assert!(!overlaps(&existing, at(2026, 9, 30, 0), at(2026, 10, 1, 0)));
+    }
+
+    #[test]
+    fn only_partitions_entirely_before_the_cutoff_expire() {
+        let existing = vec![
+            partition(
+                "t_p20261001",
+                Some((at(2026, 10, 1, 0), at(2026, 10, 2, 0))),
+            ),
+            partition(
+                "t_p20261002",
+                Some((at(2026, 10, 2, 0), at(2026, 10, 3, 0))),
+            ),
+            partition("t_default", None),
+        ];
+        let names: Vec<&str> = expired(&existing, at(2026, 10, 2, 12)).collect();
+        assert_eq!(names, ["t_p20261001"]);
+        let names: Vec<&str> = expired(&existing, at(2026, 10, 3, 0)).collect();
+        assert_eq!(names, ["t_p20261001", "t_p20261002"]);
+    }
+}
@@ -0,0 +1,263 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::metrics::metrics;
+
+/// Idle buckets are only swept once a table grows past this many keys.
+const SWEEP_THRESHOLD: usize = 4096;
+
+/// Token-bucket parameters: `per_second` sustained, up to `burst` at once.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+/// Which bucket rejected a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitScope {
+    Device,
+    Credential,
+}
+
+impl fmt::Display for LimitScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LimitScope::Device => "device",
+            LimitScope::Credential => "credential",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Limited {
+    pub scope: LimitScope,
+    pub retry_after: Duration,
+}
+
+impl Limited {
+    /// Whole seconds for the `Retry-After` header, never zero.
+    pub fn retry_after_secs(&self) -> u64 {
+        self.retry_after.as_secs_f64().ceil().max(1.0) as u64
+    }
+}
+
+impl fmt::Display for Limited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} rate limit exceeded; retry after {}s",
+            self.scope,
+            self.retry_after_secs()
+        )
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Buckets {
+    limit: RateLimit,
+    buckets: HashMap<String, Bucket>,
+}
+
+impl Buckets {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Refill the bucket for `key` and return how long until it holds a token.
+    fn wait(&mut self, key: &str, now: Instant) -> Duration {
+        let limit = self.limit;
+        if !self.buckets.contains_key(key) && self.buckets.len() >= SWEEP_THRESHOLD {
+            self.sweep(now);
+        }
+        let bucket = self.buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: f64::from(limit.burst),
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit.per_second).min(f64::from(limit.burst));
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - bucket.tokens) / limit.per_second)
+        }
+    }
+
+    fn take(&mut self, key: &str) {
+        if let Some(bucket) = self.buckets.get_mut(key) {
+            bucket.tokens -= 1.0;
+        }
+    }
+
+    /// Forget buckets that would be full by now; they behave exactly like new ones.
+    fn sweep(&mut self, now: Instant) {
+        let limit = self.limit;
+        self.buckets.retain(|_, b| {
+            let elapsed = now.saturating_duration_since(b.updated).as_secs_f64();
+            b.tokens + elapsed * limit.per_second < f64::from(limit.burst)
+        });
+    }
+}
+
+/// Token-bucket limits keyed by device and by credential. A request must fit
+/// both buckets; a rejected request consumes nothing. The default limits nothing.
+#[derive(Default)]
+pub struct RateLimiter {
+    device: Option<Mutex<Buckets>>,
+    credential: Option<Mutex<Buckets>>,
+}
+
+impl RateLimiter {
+    pub fn new(device: Option<RateLimit>, credential: Option<RateLimit>) -> Self {
+        Self {
+            device: device.map(|l| Mutex::new(Buckets::new(l))),
+            credential: credential.map(|l| Mutex::new(Buckets::new(l))),
+        }
+    }
+
+    /// Admit one event for `device` sent with `credential`, which is absent
+    /// when auth is off.
+    pub fn check(&self, device: &str, credential: Option<&str>) -> Result<(), Limited> {
+        self.check_at(device, credential, Instant::now())
+    }
+
+    fn check_at(
+        &self,
+        device: &str,
+        credential: Option<&str>,
+        now: Instant,
+    ) -> Result<(), Limited> {
+        let mut credential_bucket = match (self.credential.as_ref(), credential) {
+            (Some(buckets), Some(key)) => {
+                let mut buckets = buckets.lock().unwrap_or_else(|e| e.into_inner());
+                let wait = buckets.wait(key, now);
+                if !wait.is_zero() {
+                    return Err(self.reject(LimitScope::Credential, wait));
+                }
+                Some((buckets, key))
+            }
+            _ => None,
+        };
+
+        if let Some(buckets) = self.device.as_ref() {
+            let mut buckets = buckets.lock().unwrap_or_else(|e| e.into_inner());
+            let wait = buckets.wait(device, now);
+            if !wait.is_zero() {
+                return Err(self.reject(LimitScope::Device, wait));
+            }
+            buckets.take(device);
+        }
+        if let Some((buckets, key)) = credential_bucket.as_mut() {
+            buckets.take(key);
+        }
+        Ok(())
+    }
+
+    fn reject(&self, scope: LimitScope, retry_after: Duration) -> Limited {
+        metrics()
+            .rate_limit_rejections
+            .with_label_values(&[&scope.to_string()])
+            .inc();
+        Limited { scope, retry_after }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(per_second: f64, burst: u32) -> Option<RateLimit> {
+        Some(RateLimit { per_second, burst })
+    }
+
+    /// The process-wide rejection counter; other tests may add to it concurrently.
+    fn rejections(scope: LimitScope) -> u64 {
+        metrics()
+            .rate_limit_rejections
+            .with_label_values(&[&scope.to_string()])
+            .get()
+    }
+
+    #[test]
+    fn bucket_allows_burst_then_refills() {
+        let limiter = RateLimiter::new(limit(2.0, 3), None);
+        let start = Instant::now();
+        let rejected = rejections(LimitScope::Device);
+
+        for _ in 0..3 {
+            assert!(limiter.check_at("dev", None, start).is_ok());
+        }
+        let limited = limiter.check_at("dev", None, start).unwrap_err();
+        assert_eq!(limited.scope, LimitScope::Device);
+        assert_eq!(limited.retry_after, Duration::from_millis(500));
+        assert_eq!(limited.retry_after_secs(), 1);
+
+        // Other devices have their own bucket.
+        assert!(limiter.check_at("other", None, start).is_ok());
+
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.check_at("dev", None, later).is_ok());
+        assert!(limiter.check_at("dev", None, later).is_err());
+        assert!(rejections(LimitScope::Device) >= rejected + 2);
+    }
+
+    #[test]
+    fn credential_bucket_spans_devices_and_rejections_consume_nothing() {
+        let limiter = RateLimiter::new(limit(1.0, 1), limit(1.0, 2));
+        let now = Instant::now();
+        let rejected = rejections(LimitScope::Credential);
+
+        assert!(limiter.check_at("a", Some("tok"), now).is_ok());
+        // Device "a" is empty; the credential token must not be spent on the rejection.
+        let limited = limiter.check_at("a", Some("tok"), now).unwrap_err();
+        assert_eq!(limited.scope, LimitScope::Device);
+        assert!(limiter.check_at("b", Some("tok"), now).is_ok());
+
+        let limited = limiter.check_at("c", Some("tok"), now).unwrap_err();
+        assert_eq!(limited.scope, LimitScope::Credential);
+        assert!(rejections(LimitScope::Credential) > rejected);
+
+        // Without a credential only the device bucket applies.
+        assert!(limiter.check_at("c", None, now).is_ok());
+    }
+
+    #[test]
+    fn disabled_limiter_admits_everything() {
+        let limiter = RateLimiter::default();
+        let now = Instant::now();
+        for _ in 0..1000 {
+            assert!(limiter.check_at("dev", Some("tok"), now).is_ok());
+        }
+    }
+
+    #[test]
+    fn sweep_forgets_only_full_buckets() {
+        let mut buckets = Buckets::new(RateLimit {
+            per_second: 1.0,
+            burst: 2,
+        });
+        let now = Instant::now();
+        buckets.wait("idle", now);
+        buckets.wait("busy", now);
+        buckets.take("busy");
+
+        buckets.sweep(now);
+        assert!(!buckets.buckets.contains_key("idle"));
+        assert!(buckets.buckets.contains_key("busy"));
+
+        buckets.sweep(now + Duration::from_secs(1));
+        assert!(buckets.buckets.is_empty());
+    }
+}
@@ -10,7 +10,10 @@ use serde::Deserialize;
 use tokio::sync::RwLock;
 use tracing::warn;
 
-use crate::domain::{MovementState, OutgoingLocation};
+use crate::{
+    domain::{MovementState, OutgoingLocation},
+    metrics::metrics,
+};
 
 #[derive(Clone, Default, Debug)]
 pub struct LineTopology {
@@ -295,9 +298,23 @@ impl SegmentEstimator {
         }
     }
 
+    /// Lines in the loaded topology; zero when segment inference is disabled.
+    pub fn line_count(&self) -> usize {
+        self.topology.line_count()
+    }
+
     /// Annotate the outgoing location with the inferred segment (if available).
     pub async fn annotate(&self, loc: OutgoingLocation) -> OutgoingLocation {
         let segment = self.estimate_segment(&loc).await;
+        let result = match (&segment, self.topology.stations(loc.line_id)) {
+            (Some(_), _) => "hit",
+            (None, Some(_)) => "miss",
+            (None, None) => "unknown_line",
+        };
+        metrics()
+            .segment_inference
+            .with_label_values(&[result])
+            .inc();
         let mut enriched = loc;
 
         if let Some(seg) = segment {
@@ -0,0 +1,236 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Mutex,
+    time::Duration,
+};
+
+use axum::http::HeaderMap;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+pub const TIMESTAMP_HEADER: &str = "x-thq-timestamp";
+pub const NONCE_HEADER: &str = "x-thq-nonce";
+pub const SIGNATURE_HEADER: &str = "x-thq-signature";
+
+const MAX_NONCE_LEN: usize = 128;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Default)]
+struct SeenNonces {
+    nonces: HashSet<String>,
+    // (unix seconds after which the nonce can be forgotten, nonce), oldest first.
+    expiry: VecDeque<(i64, String)>,
+}
+
+impl SeenNonces {
+    /// Record `nonce`; false if it was already seen.
+    fn insert(&mut self, nonce: &str, now: i64, forget_after: i64) -> bool {
+        while self.expiry.front().is_some_and(|(at, _)| *at < now) {
+            if let Some((_, old)) = self.expiry.pop_front() {
+                self.nonces.remove(&old);
+            }
+        }
+        if !self.nonces.insert(nonce.to_string()) {
+            return false;
+        }
+        self.expiry.push_back((forget_after, nonce.to_string()));
+        true
+    }
+}
+
+/// Verifies HMAC-SHA256 signatures on REST ingestion requests.
+///
+/// Clients send `X-THQ-Timestamp` (unix seconds), `X-THQ-Nonce` (unique per
+/// request) and `X-THQ-Signature`, the lowercase hex HMAC of
+///
+/// ```text
+/// METHOD\nPATH\nTIMESTAMP\nNONCE\nBODY
+/// ```
+///
+/// Requests whose timestamp is more than `window` away from the server clock,
+/// or whose nonce was already used inside that window, are rejected.
+pub struct RequestVerifier {
+    secret: Vec<u8>,
+    window: i64,
+    seen: Mutex<SeenNonces>,
+}
+
+impl RequestVerifier {
+    pub fn new(secret: &[u8], window: Duration) -> Self {
+        Self {
+            secret: secret.to_vec(),
+            window: window.as_secs().max(1) as i64,
+            seen: Mutex::default(),
+        }
+    }
+
+    pub fn verify(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &HeaderMap,
+        body: &[u8],
+        now: DateTime<Utc>,
+    ) -> Result<(), String> {
+        let timestamp = required_header(headers, TIMESTAMP_HEADER)?;
+        let nonce = required_header(headers, NONCE_HEADER)?;
+        let signature = required_header(headers, SIGNATURE_HEADER)?;
+
+        let sent_at: i64 = timestamp
+            .parse()
+            .map_err(|_| format!("{TIMESTAMP_HEADER} must be unix seconds"))?;
+        let now = now.timestamp();
+        if (now - sent_at).abs() > self.window {
+            return Err("request timestamp is outside the accepted window".to_string());
+        }
+        if nonce.len() > MAX_NONCE_LEN {
+            return Err(format!(
+                "{NONCE_HEADER} must be at most {MAX_NONCE_LEN} characters"
+            ));
+        }
+        let signature =
+            hex::decode(signature).map_err(|_| format!("{SIGNATURE_HEADER} must be hex"))?;
+
+        self.mac(method, path, timestamp, nonce, body)
+            .verify_slice(&signature)
+            .map_err(|_| "invalid request signature".to_string())?;
+
+        // Only authentic requests reach the nonce cache, so forged traffic cannot fill it.
+        // Any nonce still accepted by the timestamp check expires by now + 2 * window.
+        let mut seen = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        if !seen.insert(nonce, now, now + 2 * self.window) {
+            return Err("request nonce has already been used".to_string());
+        }
+        Ok(())
+    }
+
+    fn mac(
+        &self,
+        method: &str,
+        path: &str,
+        timestamp: &str,
+        nonce: &str,
+        body: &[u8],
+    ) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("hmac accepts keys of any length");
+        mac.update(format!("{method}\n{path}\n{timestamp}\n{nonce}\n").as_bytes());
+        mac.update(body);
+        mac
+    }
+
+    #[cfg(test)]
+    pub fn sign(
+        &self,
+        method: &str,
+        path: &str,
+        timestamp: &str,
+        nonce: &str,
+        body: &[u8],
+    ) -> String {
+        hex::encode(
+            self.mac(method, path, timestamp, nonce, body)
+                .finalize()
+                .into_bytes(),
+        )
+    }
+}
+
+fn required_header<'a>(headers: &'a HeaderMap, name: &str) -> Result<&'a str, String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+        .ok_or_else(|| format!("missing {name} header"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use chrono::TimeZone;
+
+    fn signed_headers(verifier: &RequestVerifier, ts: i64, nonce: &str, body: &[u8]) -> HeaderMap {
+        let ts = ts.to_string();
+        let mut headers = HeaderMap::new();
+        headers.insert(TIMESTAMP_HEADER, HeaderValue::from_str(&ts).unwrap());
+        headers.insert(NONCE_HEADER, HeaderValue::from_str(nonce).unwrap());
+        headers.insert(
+            SIGNATURE_HEADER,
+            HeaderValue::from_str(&verifier.sign("POST", "/api/log", &ts, nonce, body)).unwrap(),
+        );
+        headers
+    }
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(secs, 0).unwrap()
+    }
+
+    #[test]
+    fn accepts_valid_signature_once() {
+        let verifier = RequestVerifier::new(b"secret", Duration::from_secs(300));
+        let body = br#"{"device":"d"}"#;
+        let headers = signed_headers(&verifier, 1_000, "n-1", body);
+
+        assert!(verifier
+            .verify("POST", "/api/log", &headers, body, at(1_010))
+            .is_ok());
+        let err = verifier
+            .verify("POST", "/api/log", &headers, body, at(1_020))
+            .unwrap_err();
+        assert!(err.contains("already been used"), "{err}");
+    }
+
+    #[test]
+    fn rejects_tampering_and_stale_timestamps() {
+        let verifier = RequestVerifier::new(b"secret", Duration::from_secs(300));
+        let body = br#"{"device":"d"}"#;
+        let headers = signed_headers(&verifier, 1_000, "n-1", body);
+
+        let tampered = verifier.verify("POST", "/api/log", &headers, b"{}", at(1_000));
+        assert_eq!(tampered.unwrap_err(), "invalid request signature");
+        let other_path = verifier.verify("POST", "/api/location", &headers, body, at(1_000));
+        assert_eq!(other_path.unwrap_err(), "invalid request signature");
+        let forged = RequestVerifier::new(b"other", Duration::from_secs(300)).verify(
+            "POST",
+            "/api/log",
+            &headers,
+            body,
+            at(1_000),
+        );
+        assert_eq!(forged.unwrap_err(), "invalid request signature");
+
+        let stale = verifier.verify("POST", "/api/log", &headers, body, at(1_301));
+        assert!(stale.unwrap_err().contains("outside the accepted window"));
+        let early = verifier.verify("POST", "/api/log", &headers, body, at(699));
+        assert!(early.unwrap_err().contains("outside the accepted window"));
+
+        let err = verifier
+            .verify("POST", "/api/log", &HeaderMap::new(), body, at(1_000))
+            .unwrap_err();
+        assert_eq!(err, "missing x-thq-timestamp header");
+    }
+
+    #[test]
+    fn nonces_are_forgotten_after_the_window() {
+        let verifier = RequestVerifier::new(b"secret", Duration::from_secs(10));
+        let body = b"{}";
+
+        let first = signed_headers(&verifier, 1_000, "n-1", body);
+        verifier
+            .verify("POST", "/api/log", &first, body, at(1_000))
+            .unwrap();
+
+        let later = signed_headers(&verifier, 1_100, "n-2", body);
+        verifier
+            .verify("POST", "/api/log", &later, body, at(1_100))
+            .unwrap();
+
+        let seen = verifier.seen.lock().unwrap();
+        assert!(!seen.nonces.contains("n-1"));
+        assert!(seen.nonces.contains("n-2"));
+        assert_eq!(seen.expiry.len(), 1);
+    }
+}
@@ -0,0 +1,451 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::domain::{OutgoingLocation, OutgoingLog};
+
+/// Drained bytes at the head of the file before it is rewritten without them
+/// (or half the size limit, for smaller spools).
+const COMPACT_MIN_BYTES: u64 = 1 << 20;
+
+/// One failed or deferred `INSERT`, replayed as a unit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "table", content = "rows", rename_all = "snake_case")]
+pub enum SpoolBatch {
+    LocationLogs(Vec<OutgoingLocation>),
+    LogEvents(Vec<OutgoingLog>),
+}
+
+impl SpoolBatch {
+    pub fn table(&self) -> &'static str {
+        match self {
+            SpoolBatch::LocationLogs(_) => "location_logs",
+            SpoolBatch::LogEvents(_) => "log_events",
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            SpoolBatch::LocationLogs(rows) => rows.len(),
+            SpoolBatch::LogEvents(rows) => rows.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+struct SpoolFile {
+    file: File,
+    len: u64,
+    /// Byte offset of the first batch not yet drained.
+    read_offset: u64,
+    pending: u64,
+}
+
+/// Bounded append-only JSON Lines file of batches waiting for PostgreSQL.
+///
+/// Batches are drained oldest first. The drained prefix is only tracked in
+/// memory until the file is compacted (rewritten without it) or truncated
+/// once fully drained, so a restart may replay a few batches that already
+/// landed; inserts are `ON CONFLICT (id, event_time) DO NOTHING` and a
+/// replayed row is byte-for-byte the original, which makes that harmless.
+pub struct Spool {
+    path: PathBuf,
+    /// Batches PostgreSQL keeps refusing; written for inspection, never read back.
+    rejected_path: PathBuf,
+    max_bytes: u64,
+    inner: Mutex<SpoolFile>,
+}
+
+impl Spool {
+    /// Open (or create) the spool at `path`; batches left by a previous run are kept.
+    pub fn open(path: impl Into<PathBuf>, max_bytes: u64) -> anyhow::Result<Self> {
+        let path = path.into();
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create spool directory {}", dir.display()))?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("failed to open spool {}", path.display()))?;
+        let mut len = file.metadata()?.len();
+        if len > 0 {
+            // Terminate a line torn by a crash so the next batch starts on its own line.
+            let mut last = [0u8];
+            file.seek(SeekFrom::End(-1))?;
+            std::io::Read::read_exact(&mut file, &mut last)?;
+            if last[0] != b'\n' {
+                file.write_all(b"\n")?;
+                len += 1;
+            }
+        }
+        let pending = count_lines(&path)?;
+        let mut rejected_path = path.clone().into_os_string();
+        rejected_path.push(".rejected");
+        Ok(Self {
+            path,
+            rejected_path: rejected_path.into(),
+            max_bytes,
+            inner: Mutex::new(SpoolFile {
+                file,
+                len,
+                read_offset: 0,
+                pending,
+            }),
+        })
+    }
+
+    /// Append a batch and flush it to disk. Fails without writing when the
+    /// undrained batches would grow past the size limit.
+    ///
+    /// Never compacts: the drainer holds offsets from [`Spool::peek`] while it
+    /// inserts, so only [`Spool::commit`] may move data within the file.
+    pub fn push(&self, batch: &SpoolBatch) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(batch).context("failed to encode spool batch")?;
+        line.push(b'\n');
+
+        let mut inner = self.lock();
+        let undrained = inner.len - inner.read_offset;
+        if undrained + line.len() as u64 > self.max_bytes {
+            anyhow::bail!(
+                "spool {} is full ({} of {} bytes)",
+                self.path.display(),
+                undrained,
+                self.max_bytes
+            );
+        }
+        inner
+            .file
+            .write_all(&line)
+            .and_then(|()| inner.file.sync_data())
+            .with_context(|| format!("failed to append to spool {}", self.path.display()))?;
+        inner.len += line.len() as u64;
+        inner.pending += 1;
+        Ok(())
+    }
+
+    /// The oldest undrained batch and the offset to pass to [`Spool::commit`]
+    /// once it is stored. Lines that fail to parse (e.g. torn by a crash) are
+    /// skipped.
+    pub fn peek(&self) -> anyhow::Result<Option<(SpoolBatch, u64)>> {
+        let mut inner = self.lock();
+        loop {
+            if inner.read_offset >= inner.len {
+                return Ok(None);
+            }
+            let mut reader = BufReader::new(
+                File::open(&self.path)
+                    .with_context(|| format!("failed to read spool {}", self.path.display()))?,
+            );
+            reader.seek(SeekFrom::Start(inner.read_offset))?;
+            let mut line = String::new();
+            let read = reader.read_line(&mut line)? as u64;
+            if read == 0 {
+                // Truncated behind our back; start over.
+                inner.len = inner.read_offset;
+                return Ok(None);
+            }
+            let next = inner.read_offset + read;
+            match serde_json::from_str(line.trim_end()) {
+                Ok(batch) => return Ok(Some((batch, next))),
+                Err(err) => {
+                    tracing::warn!(
+                        ?err,
+                        offset = inner.read_offset,
+                        "skipping unreadable spool entry"
+                    );
+                    self.advance(&mut inner, next)?;
+                }
+            }
+        }
+    }
+
+    /// Set a batch aside in `<path>.rejected` so it stops blocking the ones
+    /// behind it. The file is left for an operator to inspect or replay.
+    pub fn quarantine(&self, batch: &SpoolBatch) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(batch).context("failed to encode spool batch")?;
+        line.push(b'\n');
+
+        // Held so concurrent quarantines do not interleave their lines.
+        let _inner = self.lock();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.rejected_path)
+            .with_context(|| format!("failed to open {}", self.rejected_path.display()))?;
+        file.write_all(&line)
+            .and_then(|()| file.sync_data())
+            .with_context(|| format!("failed to append to {}", self.rejected_path.display()))
+    }
+
+    pub fn rejected_path(&self) -> &Path {
+        &self.rejected_path
+    }
+
+    /// Mark everything before `offset` as stored.
+    pub fn commit(&self, offset: u64) -> anyhow::Result<()> {
+        let mut inner = self.lock();
+        self.advance(&mut inner, offset)
+    }
+
+    fn advance(&self, inner: &mut SpoolFile, offset: u64) -> anyhow::Result<()> {
+        inner.read_offset = offset;
+        inner.pending = inner.pending.saturating_sub(1);
+        if inner.read_offset >= inner.len {
+            inner.file.set_len(0).context("failed to truncate spool")?;
+            inner.len = 0;
+            inner.read_offset = 0;
+            inner.pending = 0;
+        } else if inner.read_offset >= COMPACT_MIN_BYTES.min(self.max_bytes / 2)
+            && inner.read_offset * 2 >= inner.len
+        {
+            self.compact(inner)?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite the file without its drained prefix. The copy replaces the
+    /// spool by rename, so a crash leaves either the old file or the new one.
+    fn compact(&self, inner: &mut SpoolFile) -> anyhow::Result<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".compact");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut src = File::open(&self.path)
+            .with_context(|| format!("failed to read spool {}", self.path.display()))?;
+        src.seek(SeekFrom::Start(inner.read_offset))?;
+        let mut dst = File::create(&tmp_path)
+            .with_context(|| format!("failed to create {}", tmp_path.display()))?;
+        let copied = io::copy(&mut src, &mut dst)
+            .and_then(|copied| dst.sync_all().map(|()| copied))
+            .with_context(|| format!("failed to compact spool {}", self.path.display()))?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("failed to replace spool {}", self.path.display()))?;
+
+        inner.file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("failed to reopen spool {}", self.path.display()))?;
+        inner.len = copied;
+        inner.read_offset = 0;
+        Ok(())
+    }
+
+    /// Batches waiting to be drained.
+    pub fn pending(&self) -> u64 {
+        self.lock().pending
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending() == 0
+    }
+
+    /// Bytes on disk, including a drained prefix not yet compacted away.
+    pub fn bytes(&self) -> u64 {
+        self.lock().len
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, SpoolFile> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn count_lines(path: &Path) -> anyhow::Result<u64> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut count = 0;
+    for line in reader.split(b'\n') {
+        if !line?.is_empty() {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{LogBody, LogLevel, LogType};
+    use uuid::Uuid;
+
+    fn tmp_spool() -> PathBuf {
+        std::env::temp_dir().join(format!("spool_{}.jsonl", Uuid::new_v4()))
+    }
+
+    fn logs(id: &str) -> SpoolBatch {
+        SpoolBatch::LogEvents(vec![OutgoingLog {
+            id: id.to_string(),
+            device: "dev".to_string(),
+            timestamp: 1,
+            log: LogBody {
+                r#type: LogType::App,
+                level: LogLevel::Info,
+                message: id.to_string(),
+            },
+        }])
+    }
+
+    fn first_id(batch: &SpoolBatch) -> &str {
+        match batch {
+            SpoolBatch::LogEvents(rows) => &rows[0].id,
+            SpoolBatch::LocationLogs(rows) => &rows[0].id,
+        }
+    }
+
+    #[test]
+    fn drains_in_order_and_truncates_when_empty() {
+        let path = tmp_spool();
+        let spool = Spool::open(&path, 1 << 20).unwrap();
+        spool.push(&logs("a")).unwrap();
+        spool.push(&logs("b")).unwrap();
+        assert_eq!(spool.pending(), 2);
+
+        let (batch, next) = spool.peek().unwrap().unwrap();
+        assert_eq!(first_id(&batch), "a");
+        // Peeking again without committing returns the same batch.
+        assert_eq!(first_id(&spool.peek().unwrap().unwrap().0), "a");
+        spool.commit(next).unwrap();
+
+        spool.push(&logs("c")).unwrap();
+        let (batch, next) = spool.peek().unwrap().unwrap();
+        assert_eq!(first_id(&batch), "b");
+        spool.commit(next).unwrap();
+        let (batch, next) = spool.peek().unwrap().unwrap();
+        assert_eq!(first_id(&batch), "c");
+        spool.commit(next).unwrap();
+
+        assert!(spool.peek().unwrap().is_none());
+        assert!(spool.is_empty());
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn reopening_replays_undrained_batches() {
+        let path = tmp_spool();
+        {
+            let spool = Spool::open(&path, 1 << 20).unwrap();
+            spool.push(&logs("a")).unwrap();
+            spool.push(&logs("b")).unwrap();
+        }
+        // Simulate a crash halfway through writing a line.
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"table\":\"log_ev")
+            .unwrap();
+
+        let spool = Spool::open(&path, 1 << 20).unwrap();
+        assert_eq!(spool.pending(), 3);
+        spool.push(&logs("c")).unwrap();
+        let (batch, next) = spool.peek().unwrap().unwrap();
+        assert_eq!(first_id(&batch), "a");
+        spool.commit(next).unwrap();
+        let (batch, next) = spool.peek().unwrap().unwrap();
+        assert_eq!(first_id(&batch), "b");
+        spool.commit(next).unwrap();
+        // The torn line is skipped and the batch written after it survives.
+        let (batch, next) = spool.peek().unwrap().unwrap();
+        assert_eq!(first_id(&batch), "c");
+        spool.commit(next).unwrap();
+        assert!(spool.peek().unwrap().is_none());
+        assert!(spool.is_empty());
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn quarantined_batches_go_to_a_side_file() {
+        let path = tmp_spool();
+        let spool = Spool::open(&path, 1 << 20).unwrap();
+        spool.push(&logs("a")).unwrap();
+        spool.push(&logs("b")).unwrap();
+
+        let (batch, next) = spool.peek().unwrap().unwrap();
+        spool.quarantine(&batch).unwrap();
+        spool.commit(next).unwrap();
+        assert_eq!(first_id(&spool.peek().unwrap().unwrap().0), "b");
+
+        let rejected = fs::read_to_string(spool.rejected_path()).unwrap();
+        let batch: SpoolBatch = serde_json::from_str(rejected.trim_end()).unwrap();
+        assert_eq!(first_id(&batch), "a");
+        let _ = fs::remove_file(spool.rejected_path());
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn push_fails_once_the_size_limit_is_reached() {
+        let path = tmp_spool();
+        let spool = Spool::open(&path, 200).unwrap();
+        spool.push(&logs("a")).unwrap();
+        let err = spool.push(&logs("b")).unwrap_err();
+        assert!(err.to_string().contains("is full"), "{err}");
+        assert_eq!(spool.pending(), 1);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn pushes_while_a_batch_is_in_flight_keep_its_offset_valid() {
+        let path = tmp_spool();
+        let line = serde_json::to_vec(&logs("0")).unwrap().len() as u64 + 1;
+        let spool = Spool::open(&path, 3 * line).unwrap();
+        spool.push(&logs("0")).unwrap();
+        spool.push(&logs("1")).unwrap();
+        spool.commit(spool.peek().unwrap().unwrap().1).unwrap();
+
+        // "1" is being inserted while ingestion fills the spool up.
+        let (in_flight, next) = spool.peek().unwrap().unwrap();
+        assert_eq!(first_id(&in_flight), "1");
+        let mut pushed = vec!["1".to_string()];
+        for i in 2.. {
+            if spool.push(&logs(&i.to_string())).is_err() {
+                break;
+            }
+            pushed.push(i.to_string());
+        }
+        assert_eq!(pushed, ["1", "2", "3"]);
+        spool.commit(next).unwrap();
+        // Committing "1" compacted "0" and "1" away.
+        assert_eq!(spool.bytes(), 2 * line);
+
+        let mut drained = vec!["1".to_string()];
+        while let Some((batch, next)) = spool.peek().unwrap() {
+            drained.push(first_id(&batch).to_string());
+            spool.commit(next).unwrap();
+        }
+        assert_eq!(drained, pushed);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn drained_batches_do_not_count_towards_the_limit() {
+        let path = tmp_spool();
+        let line = serde_json::to_vec(&logs("a")).unwrap().len() as u64 + 1;
+        let spool = Spool::open(&path, 2 * line).unwrap();
+        spool.push(&logs("a")).unwrap();
+        spool.push(&logs("b")).unwrap();
+        let (_, next) = spool.peek().unwrap().unwrap();
+        spool.commit(next).unwrap();
+
+        // Only "b" is pending, so "c" fits once the drained "a" is compacted away.
+        spool.push(&logs("c")).unwrap();
+        assert_eq!(spool.bytes(), 2 * line);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 2 * line);
+        let (batch, next) = spool.peek().unwrap().unwrap();
+        assert_eq!(first_id(&batch), "b");
+        spool.commit(next).unwrap();
+        assert_eq!(first_id(&spool.peek().unwrap().unwrap().0), "c");
+        let _ = fs::remove_file(path);
+    }
+}
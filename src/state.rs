@@ -1,107 +1,1133 @@
-use std::{collections::HashMap, collections::VecDeque, sync::Arc};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use axum::extract::ws::Message;
-use tokio::sync::{mpsc, RwLock};
+use serde::Deserialize;
+use tokio::sync::{
+    broadcast::{self, error::RecvError, error::TryRecvError},
+    Mutex as AsyncMutex, Notify,
+};
 use uuid::Uuid;
 
-pub type ClientTx = mpsc::Sender<Message>;
+use crate::domain::{
+    sequenced_json, EventKind, EventMeta, GapReason, OutgoingGap, OutgoingMessage,
+    SubscriptionFilter,
+};
+use crate::metrics::metrics;
 
+/// Broadcasts a subscriber may fall behind the feed, beyond its own queue
+/// size, before the oldest ones are lost without the policy seeing them.
+const FEED_HEADROOM: usize = 1024;
+
+/// What to do when a subscriber's outbox is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlowConsumerPolicy {
+    /// Discard the oldest queued frame and tell the client with a `gap` frame.
+    #[default]
+    DropOldest,
+    /// Drop new frames and close the connection once `max_drops` is reached.
+    Disconnect,
+    /// Drop a queued location update from the same device and queue the newer
+    /// one at the back, so frames stay in seq order; falls back to drop-oldest
+    /// when there is nothing to replace.
+    Coalesce,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SlowConsumerConfig {
+    pub policy: SlowConsumerPolicy,
+    pub queue_size: usize,
+    pub max_drops: u64,
+}
+
+impl Default for SlowConsumerConfig {
+    fn default() -> Self {
+        Self {
+            policy: SlowConsumerPolicy::DropOldest,
+            queue_size: 256,
+            max_drops: 1000,
+        }
+    }
+}
+
+type Feed = broadcast::Receiver<Arc<BufferedEvent>>;
+
+/// A feed handed to an outbox by the hub; `None` detaches it.
+struct Attach {
+    feed: Option<Feed>,
+    /// Seq of the last broadcast before the feed was subscribed.
+    cursor: u64,
+    /// Replayed frames to send before anything from the feed.
+    replay: Vec<Arc<str>>,
+}
+
+#[derive(Default)]
+struct OutboxState {
+    /// Set by the hub on (un)subscribe and picked up by the next `recv`.
+    attach: Option<Attach>,
+    client: Option<Uuid>,
+    filter: SubscriptionFilter,
+    /// Seq of the last event pulled from the feed.
+    cursor: u64,
+    replay: VecDeque<Arc<str>>,
+    queue: VecDeque<Arc<BufferedEvent>>,
+    // Pending gap notice as (from_seq, to_seq, count) for frames dropped since the last read.
+    gap: Option<(u64, u64, u64)>,
+    dropped_total: u64,
+    // Part of `dropped_total` already added to the metrics.
+    dropped_reported: u64,
+    closed: bool,
+}
+
+/// Per-subscriber reader of the hub's broadcast feed.
+///
+/// Publishers only append to the shared feed. Each outbox pulls from it when
+/// its connection asks for the next frame, keeps the events that pass its
+/// filter, and applies the hub's [`SlowConsumerPolicy`] to whatever backlog
+/// built up since the previous read.
+pub struct Outbox {
+    state: Mutex<OutboxState>,
+    /// Only the task calling [`Outbox::recv`] reads the feed.
+    feed: AsyncMutex<Option<Feed>>,
+    notify: Notify,
+    config: SlowConsumerConfig,
+}
+
+impl Outbox {
+    fn new(config: SlowConsumerConfig) -> Self {
+        Self {
+            state: Mutex::new(OutboxState::default()),
+            feed: AsyncMutex::new(None),
+            notify: Notify::new(),
+            config,
+        }
+    }
+
+    fn attach(&self, client: Uuid, feed: Option<Feed>, cursor: u64, replay: Vec<Arc<str>>) {
+        let mut st = self.lock();
+        st.client = Some(client);
+        st.attach = Some(Attach {
+            feed,
+            cursor,
+            replay,
+        });
+        drop(st);
+        self.notify.notify_one();
+    }
+
+    fn set_filter(&self, filter: SubscriptionFilter) {
+        self.lock().filter = filter;
+    }
+
+    /// Next frame to write, preceded by a `gap` notice after drops.
+    /// Returns `None` once the outbox is closed and drained of notices.
+    pub async fn recv(&self) -> Option<Arc<str>> {
+        let mut feed = self.feed.lock().await;
+        loop {
+            {
+                let mut st = self.lock();
+                if let Some(attach) = st.attach.take() {
+                    *feed = attach.feed;
+                    st.cursor = attach.cursor;
+                    st.replay.extend(attach.replay);
+                }
+                let mut ended = false;
+                if let Some(rx) = feed.as_mut() {
+                    loop {
+                        match rx.try_recv() {
+                            Ok(event) => st.take(event),
+                            Err(TryRecvError::Lagged(n)) => st.lagged(n),
+                            Err(TryRecvError::Empty) => break,
+                            Err(TryRecvError::Closed) => {
+                                ended = true;
+                                break;
+                            }
+                        }
+                    }
+                }
+                if ended {
+                    *feed = None;
+                }
+                st.enforce(&self.config);
+
+                if st.closed {
+                    *feed = None;
+                    return None;
+                }
+                if let Some(frame) = st.replay.pop_front() {
+                    return Some(frame);
+                }
+                if let Some((from_seq, to_seq, dropped)) = st.gap.take() {
+                    let notice = OutgoingMessage::Gap(OutgoingGap {
+                        from_seq: Some(from_seq),
+                        to_seq: Some(to_seq),
+                        reason: GapReason::SlowConsumer,
+                        dropped: Some(dropped),
+                        epoch: None,
+                    });
+                    match serde_json::to_string(&notice) {
+                        Ok(json) => return Some(json.into()),
+                        Err(err) => tracing::error!(?err, "failed to serialize gap notice"),
+                    }
+                }
+                if let Some(event) = st.queue.pop_front() {
+                    return Some(event.payload.clone());
+                }
+            }
+
+            match feed.as_mut() {
+                Some(rx) => tokio::select! {
+                    received = rx.recv() => match received {
+                        Ok(event) => self.lock().take(event),
+                        Err(RecvError::Lagged(n)) => self.lock().lagged(n),
+                        Err(RecvError::Closed) => *feed = None,
+                    },
+                    _ = self.notify.notified() => {}
+                },
+                None => self.notify.notified().await,
+            }
+        }
+    }
+
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.notify.notify_one();
+    }
+
+    /// Frames dropped for this subscriber since it connected.
+    pub fn dropped(&self) -> u64 {
+        self.lock().dropped_total
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, OutboxState> {
+        self.state.lock().expect("outbox lock poisoned")
+    }
+}
+
+impl OutboxState {
+    fn take(&mut self, event: Arc<BufferedEvent>) {
+        self.cursor = event.seq;
+        if self.filter.matches(&event.meta) {
+            self.queue.push_back(event);
+        }
+    }
+
+    /// The feed overwrote `n` events this subscriber had not read yet. They
+    /// are counted as dropped whether or not they matched the filter.
+    fn lagged(&mut self, n: u64) {
+        let (from, to) = (self.cursor + 1, self.cursor + n);
+        self.cursor = to;
+        self.dropped_total += n;
+        self.gap = Some(match self.gap {
+            Some((f, t, count)) => (f.min(from), t.max(to), count + n),
+            None => (from, to, n),
+        });
+    }
+
+    /// Apply the slow-consumer policy to the frames queued beyond `queue_size`.
+    fn enforce(&mut self, config: &SlowConsumerConfig) {
+        let excess = self.queue.len().saturating_sub(config.queue_size.max(1));
+        match config.policy {
+            SlowConsumerPolicy::DropOldest => {
+                for _ in 0..excess {
+                    self.drop_oldest();
+                }
+            }
+            SlowConsumerPolicy::Coalesce => {
+                let coalesced = self.coalesce(excess);
+                for _ in coalesced..excess {
+                    self.drop_oldest();
+                }
+            }
+            SlowConsumerPolicy::Disconnect => {
+                for _ in 0..excess {
+                    if let Some(newest) = self.queue.pop_back() {
+                        self.record_drop(newest.seq);
+                    }
+                }
+                if !self.closed && self.dropped_total >= config.max_drops {
+                    self.closed = true;
+                    metrics().slow_consumer_disconnects.inc();
+                    tracing::warn!(client = ?self.client, dropped = self.dropped_total, "disconnecting slow subscriber");
+                }
+            }
+        }
+
+        let dropped = self.dropped_total;
+        let before = self.dropped_reported;
+        if dropped > before {
+            metrics().broadcast_dropped.inc_by(dropped - before);
+            self.dropped_reported = dropped;
+            // Log the first drop and then every 100th to keep noisy viewers visible but quiet.
+            if before == 0 || dropped / 100 > before / 100 {
+                tracing::warn!(client = ?self.client, dropped, policy = ?config.policy, "subscriber is falling behind");
+            }
+        }
+    }
+
+    /// Drop up to `limit` queued location updates that a newer one from the
+    /// same device supersedes, oldest first. Returns how many were dropped.
+    fn coalesce(&mut self, limit: usize) -> usize {
+        let mut newest: HashMap<&str, u64> = HashMap::new();
+        for event in &self.queue {
+            if let Some(key) = event.coalesce_key.as_deref() {
+                newest.insert(key, event.seq);
+            }
+        }
+        let superseded: Vec<u64> = self
+            .queue
+            .iter()
+            .filter(|event| {
+                event
+                    .coalesce_key
+                    .as_deref()
+                    .is_some_and(|key| newest[key] != event.seq)
+            })
+            .map(|event| event.seq)
+            .take(limit)
+            .collect();
+        self.queue.retain(|event| !superseded.contains(&event.seq));
+        for seq in &superseded {
+            self.record_drop(*seq);
+        }
+        superseded.len()
+    }
+
+    fn drop_oldest(&mut self) {
+        if let Some(old) = self.queue.pop_front() {
+            self.record_drop(old.seq);
+        }
+    }
+
+    fn record_drop(&mut self, seq: u64) {
+        self.dropped_total += 1;
+        self.gap = Some(match self.gap {
+            Some((from, to, n)) => (from.min(seq), to.max(seq), n + 1),
+            None => (seq, seq, 1),
+        });
+    }
+}
+
+struct BufferedEvent {
+    seq: u64,
+    id: Option<String>,
+    meta: EventMeta,
+    // Device id for location updates, used by the coalesce policy.
+    coalesce_key: Option<Arc<str>>,
+    payload: Arc<str>,
+}
+
+struct RingBuffer {
+    events: VecDeque<Arc<BufferedEvent>>,
+    // Sequence numbers start at 1 so `since_seq = 0` means "everything".
+    next_seq: u64,
+    // Identifies this run of the server; seqs are only comparable within one epoch.
+    epoch: u64,
+    last_broadcast: Option<Instant>,
+    // Seqs of events whose rows may not be in PostgreSQL yet; see `PendingWrite`.
+    unpersisted: BTreeSet<u64>,
+}
+
+impl RingBuffer {
+    /// Buffered events that pass `filter`, oldest first. With `since_seq`, only
+    /// events after that sequence number are returned and a gap is reported
+    /// when part of the requested tail has already been evicted. A `since_seq`
+    /// from another `epoch` (or one this hub never issued) is a reset: the whole
+    /// buffer is replayed. Events whose id is in `seen` (e.g. already sent from
+    /// the database backfill) are skipped.
+    fn replay(
+        &self,
+        filter: &SubscriptionFilter,
+        since_seq: Option<u64>,
+        epoch: Option<u64>,
+        seen: &HashSet<String>,
+    ) -> Replay {
+        let latest = self.next_seq - 1;
+        let oldest = self.events.front().map_or(latest + 1, |ev| ev.seq);
+        // Seqs before `oldest` that are no longer buffered, if any.
+        let missing = |from: u64| (oldest > from).then_some((from, oldest - 1));
+
+        let (after, gap) = match since_seq {
+            None => (0, None),
+            Some(seen) if seen > latest || epoch.is_some_and(|e| e != self.epoch) => {
+                let range = missing(1);
+                let gap = OutgoingGap {
+                    from_seq: range.map(|(from, _)| from),
+                    to_seq: range.map(|(_, to)| to),
+                    reason: GapReason::Reset,
+                    dropped: None,
+                    epoch: Some(self.epoch),
+                };
+                (0, Some(gap))
+            }
+            Some(seen) => {
+                let gap = missing(seen + 1).map(|(from, to)| OutgoingGap {
+                    from_seq: Some(from),
+                    to_seq: Some(to),
+                    reason: GapReason::Evicted,
+                    dropped: None,
+                    epoch: None,
+                });
+                (seen, gap)
+            }
+        };
+
+        Replay {
+            gap,
+            events: self
+                .events
+                .iter()
+                .filter(|ev| ev.seq > after && filter.matches(&ev.meta))
+                .filter(|ev| !ev.id.as_ref().is_some_and(|id| seen.contains(id)))
+                .map(|ev| ev.payload.clone())
+                .collect(),
+        }
+    }
+}
+
+/// Buffered events to send to a (re)subscribing client.
+#[derive(Debug)]
+struct Replay {
+    /// Missed range the buffer can no longer serve.
+    gap: Option<OutgoingGap>,
+    events: Vec<Arc<str>>,
+}
+
+/// Boot time in milliseconds, so every server run gets a new, increasing epoch.
+fn boot_epoch() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Keeps a broadcast event counted as not yet persisted until dropped, so
+/// [`TelemetryHub::persisted_seq`] stays below it. Storage holds it until the
+/// row is inserted or given up on.
+pub struct PendingWrite {
+    buffer: Arc<Mutex<RingBuffer>>,
+    seq: u64,
+}
+
+impl PendingWrite {
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+}
+
+impl Drop for PendingWrite {
+    fn drop(&mut self) {
+        if let Ok(mut buf) = self.buffer.lock() {
+            buf.unpersisted.remove(&self.seq);
+        }
+    }
+}
+
+/// Fan-out point between ingestion and subscribers.
+///
+/// A broadcast serializes the message once, appends it to the ring buffer and
+/// to a `tokio::sync::broadcast` feed, and returns: the critical section is
+/// constant-time whatever the number of subscribers. Each [`Outbox`] reads the
+/// feed lazily from its own connection task, so filtering and slow-consumer
+/// handling never hold up publishers.
 #[derive(Clone)]
 pub struct TelemetryHub {
-    subscribers: Arc<RwLock<HashMap<Uuid, ClientTx>>>,
-    buffer: Arc<RwLock<VecDeque<String>>>,
+    subscribers: Arc<RwLock<HashMap<Uuid, Arc<Outbox>>>>,
+    buffer: Arc<Mutex<RingBuffer>>,
+    feed: broadcast::Sender<Arc<BufferedEvent>>,
     capacity: usize,
+    slow_consumer: SlowConsumerConfig,
 }
 
 impl TelemetryHub {
     pub fn new(capacity: usize) -> Self {
+        let slow_consumer = SlowConsumerConfig::default();
         Self {
             subscribers: Arc::new(RwLock::new(HashMap::new())),
-            buffer: Arc::new(RwLock::new(VecDeque::with_capacity(capacity))),
+            buffer: Arc::new(Mutex::new(RingBuffer {
+                events: VecDeque::with_capacity(capacity),
+                next_seq: 1,
+                epoch: boot_epoch(),
+                last_broadcast: None,
+                unpersisted: BTreeSet::new(),
+            })),
+            feed: broadcast::channel(slow_consumer.queue_size.max(1) + FEED_HEADROOM).0,
             capacity,
+            slow_consumer,
         }
     }
 
-    pub async fn add_subscriber(&self, id: Uuid, tx: ClientTx) {
-        let mut subs = self.subscribers.write().await;
-        subs.insert(id, tx);
+    pub fn with_slow_consumer(mut self, config: SlowConsumerConfig) -> Self {
+        self.slow_consumer = config;
+        self.feed = broadcast::channel(config.queue_size.max(1) + FEED_HEADROOM).0;
+        self
     }
 
-    pub async fn remove_subscriber(&self, id: &Uuid) {
-        let mut subs = self.subscribers.write().await;
-        subs.remove(id);
+    /// Create an outbox for a new connection using the hub's slow-consumer settings.
+    pub fn new_outbox(&self) -> Arc<Outbox> {
+        Arc::new(Outbox::new(self.slow_consumer))
     }
 
-    pub async fn snapshot(&self) -> Vec<String> {
-        let buf = self.buffer.read().await;
-        buf.iter().cloned().collect()
-    }
+    /// Register `outbox` and queue what it missed ahead of live events. The
+    /// replay is taken and the feed subscribed under the buffer lock, so every
+    /// later broadcast arrives exactly once, after the replay, in seq order.
+    pub fn subscribe(
+        &self,
+        id: Uuid,
+        outbox: Arc<Outbox>,
+        filter: SubscriptionFilter,
+        since_seq: Option<u64>,
+        epoch: Option<u64>,
+        seen: &HashSet<String>,
+    ) {
+        let (feed, cursor, replay) = {
+            let buf = self.buffer.lock().expect("ring buffer lock poisoned");
+            (
+                self.feed.subscribe(),
+                buf.next_seq - 1,
+                buf.replay(&filter, since_seq, epoch, seen),
+            )
+        };
 
-    pub async fn broadcast(&self, msg: String) {
-        {
-            let mut buf = self.buffer.write().await;
-            if buf.len() >= self.capacity {
-                buf.pop_front();
+        let mut frames = Vec::with_capacity(replay.events.len() + 1);
+        if let Some(gap) = replay.gap {
+            match serde_json::to_string(&OutgoingMessage::Gap(gap)) {
+                Ok(json) => frames.push(json.into()),
+                Err(err) => tracing::error!(?err, "failed to serialize gap notice"),
             }
-            buf.push_back(msg.clone());
         }
+        frames.extend(replay.events);
 
-        let targets = {
-            let subs = self.subscribers.read().await;
-            subs.iter()
-                .map(|(id, tx)| (*id, tx.clone()))
-                .collect::<Vec<_>>()
-        };
+        outbox.set_filter(filter);
+        outbox.attach(id, Some(feed), cursor, frames);
+        let mut subs = self.subscribers.write().expect("subscriber lock poisoned");
+        subs.insert(id, outbox);
+    }
 
-        let mut stale = Vec::new();
-        for (id, tx) in targets {
-            if tx.is_closed() {
-                stale.push(id);
-                continue;
-            }
-            if let Err(err) = tx.try_send(Message::Text(msg.clone())) {
-                tracing::warn!(?id, "broadcast send failed: {err}");
+    /// Register without replaying anything.
+    #[cfg(test)]
+    pub fn add_subscriber(&self, id: Uuid, outbox: Arc<Outbox>, filter: SubscriptionFilter) {
+        let latest = self.latest_seq();
+        self.subscribe(id, outbox, filter, Some(latest), None, &HashSet::new());
+    }
+
+    /// Swap the filter of an existing subscriber. Returns `false` if `id` is not subscribed.
+    pub fn update_subscriber(&self, id: &Uuid, filter: SubscriptionFilter) -> bool {
+        let subs = self.subscribers.read().expect("subscriber lock poisoned");
+        match subs.get(id) {
+            Some(outbox) => {
+                outbox.set_filter(filter);
+                true
             }
+            None => false,
+        }
+    }
+
+    /// Stop feeding `id`; frames it already queued are still delivered.
+    pub fn remove_subscriber(&self, id: &Uuid) {
+        let mut subs = self.subscribers.write().expect("subscriber lock poisoned");
+        if let Some(outbox) = subs.remove(id) {
+            outbox.attach(*id, None, 0, Vec::new());
         }
+    }
+
+    #[cfg(test)]
+    pub fn snapshot(&self) -> Vec<Arc<str>> {
+        self.replay(&SubscriptionFilter::default(), None, None, &HashSet::new())
+            .events
+    }
+
+    #[cfg(test)]
+    fn replay(
+        &self,
+        filter: &SubscriptionFilter,
+        since_seq: Option<u64>,
+        epoch: Option<u64>,
+        seen: &HashSet<String>,
+    ) -> Replay {
+        self.buffer
+            .lock()
+            .expect("ring buffer lock poisoned")
+            .replay(filter, since_seq, epoch, seen)
+    }
 
-        if !stale.is_empty() {
-            let mut subs = self.subscribers.write().await;
-            for id in stale {
-                subs.remove(&id);
+    /// Connections currently registered with the hub.
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers
+            .read()
+            .expect("subscriber lock poisoned")
+            .len()
+    }
+
+    /// Events currently held in the ring buffer.
+    pub fn buffered_len(&self) -> usize {
+        self.buffer
+            .lock()
+            .expect("ring buffer lock poisoned")
+            .events
+            .len()
+    }
+
+    /// Time since the last event was broadcast, or `None` if nothing was yet.
+    pub fn last_broadcast_age(&self) -> Option<Duration> {
+        self.buffer
+            .lock()
+            .expect("ring buffer lock poisoned")
+            .last_broadcast
+            .map(|at| at.elapsed())
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Epoch stamped on every broadcast; changes when the server restarts.
+    pub fn epoch(&self) -> u64 {
+        self.buffer.lock().expect("ring buffer lock poisoned").epoch
+    }
+
+    /// Sequence number of the most recent broadcast (0 before the first one).
+    #[cfg(test)]
+    pub fn latest_seq(&self) -> u64 {
+        self.buffer
+            .lock()
+            .expect("ring buffer lock poisoned")
+            .next_seq
+            - 1
+    }
+
+    /// Highest seq up to which every broadcast event is in PostgreSQL (or was
+    /// given up on). Later events may only be in the ring buffer.
+    pub fn persisted_seq(&self) -> u64 {
+        let buf = self.buffer.lock().expect("ring buffer lock poisoned");
+        buf.unpersisted
+            .first()
+            .map_or(buf.next_seq - 1, |oldest| oldest - 1)
+    }
+
+    /// Send `message` to subscribers. Its seq counts as unpersisted until the
+    /// returned guard is dropped; hand it to storage along with the row.
+    pub fn broadcast(&self, message: &OutgoingMessage) -> Option<PendingWrite> {
+        let meta = EventMeta::from(message);
+        let body = match serde_json::to_string(message) {
+            Ok(v) => v,
+            Err(err) => {
+                tracing::error!(?err, "failed to serialize broadcast message");
+                return None;
             }
+        };
+        let coalesce_key: Option<Arc<str>> = match meta.kind {
+            Some(EventKind::LocationUpdate) => meta.device.as_deref().map(Arc::from),
+            _ => None,
+        };
+
+        let id = message.event_id().map(str::to_string);
+
+        let mut buf = self.buffer.lock().expect("ring buffer lock poisoned");
+        let seq = buf.next_seq;
+        buf.next_seq += 1;
+        buf.last_broadcast = Some(Instant::now());
+        buf.unpersisted.insert(seq);
+        let event = Arc::new(BufferedEvent {
+            seq,
+            id,
+            meta,
+            coalesce_key,
+            payload: sequenced_json(seq, buf.epoch, &body).into(),
+        });
+        if buf.events.len() >= self.capacity {
+            buf.events.pop_front();
         }
+        buf.events.push_back(event.clone());
+        // Sent under the buffer lock so the feed carries events in seq order.
+        // It only fails when nobody is subscribed.
+        let _ = self.feed.send(event);
+        drop(buf);
+        Some(PendingWrite {
+            buffer: self.buffer.clone(),
+            seq,
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tokio::sync::mpsc;
+    use crate::domain::{
+        sequenced_seq, LogBody, LogLevel, LogType, MovementState, OutgoingCoords, OutgoingLocation,
+        OutgoingLog,
+    };
+
+    fn log_message(device: &str, level: LogLevel, message: &str) -> OutgoingMessage {
+        OutgoingMessage::Log(OutgoingLog {
+            id: message.to_string(),
+            device: device.to_string(),
+            timestamp: 0,
+            log: LogBody {
+                r#type: LogType::App,
+                level,
+                message: message.to_string(),
+            },
+        })
+    }
+
+    fn message_text(msg: Arc<str>) -> serde_json::Value {
+        serde_json::from_str(&msg).unwrap()
+    }
+
+    fn location_message(device: &str, timestamp: u64) -> OutgoingMessage {
+        OutgoingMessage::LocationUpdate(OutgoingLocation {
+            id: format!("{device}-{timestamp}"),
+            device: device.to_string(),
+            state: MovementState::Moving,
+            station_id: None,
+            line_id: 1,
+            coords: OutgoingCoords {
+                latitude: 35.0,
+                longitude: 139.0,
+                accuracy: None,
+                speed: None,
+            },
+            timestamp,
+            segment_id: None,
+            from_station_id: None,
+            to_station_id: None,
+            battery_level: None,
+            battery_state: None,
+        })
+    }
+
+    fn hub_with_policy(policy: SlowConsumerPolicy, queue_size: usize) -> TelemetryHub {
+        TelemetryHub::new(10).with_slow_consumer(SlowConsumerConfig {
+            policy,
+            queue_size,
+            max_drops: 2,
+        })
+    }
 
     #[tokio::test]
     async fn broadcast_reaches_active_subscriber() {
         let hub = TelemetryHub::new(10);
-        let (tx, mut rx) = mpsc::channel(4);
+        let outbox = hub.new_outbox();
         let client_id = Uuid::new_v4();
-        hub.add_subscriber(client_id, tx).await;
+        hub.add_subscriber(client_id, outbox.clone(), SubscriptionFilter::default());
 
-        hub.broadcast("hello".to_string()).await;
+        hub.broadcast(&log_message("dev", LogLevel::Info, "hello"));
 
-        let msg = rx.recv().await.expect("message should arrive");
-        match msg {
-            Message::Text(t) => assert_eq!(t, "hello"),
-            _ => panic!("expected text message"),
-        }
+        let msg = outbox.recv().await.expect("message should arrive");
+        assert_eq!(message_text(msg)["log"]["message"], "hello");
+    }
+
+    #[tokio::test]
+    async fn broadcast_skips_subscribers_whose_filter_rejects() {
+        let hub = TelemetryHub::new(10);
+        let outbox = hub.new_outbox();
+        let filter = SubscriptionFilter {
+            devices: vec!["wanted".into()],
+            ..Default::default()
+        };
+        hub.add_subscriber(Uuid::new_v4(), outbox.clone(), filter);
+
+        hub.broadcast(&log_message("other", LogLevel::Info, "skip"));
+        hub.broadcast(&log_message("wanted", LogLevel::Info, "keep"));
+
+        let msg = outbox.recv().await.expect("message should arrive");
+        assert_eq!(message_text(msg)["log"]["message"], "keep");
+        assert!(outbox.state.lock().unwrap().queue.is_empty());
     }
 
     #[tokio::test]
-    async fn ring_buffer_drops_oldest_when_full() {
+    async fn update_subscriber_swaps_filter() {
+        let hub = TelemetryHub::new(10);
+        let outbox = hub.new_outbox();
+        let client_id = Uuid::new_v4();
+        let only = |device: &str| SubscriptionFilter {
+            devices: vec![device.into()],
+            ..Default::default()
+        };
+        hub.add_subscriber(client_id, outbox.clone(), only("a"));
+
+        assert!(hub.update_subscriber(&client_id, only("b")));
+        assert!(!hub.update_subscriber(&Uuid::new_v4(), only("b")));
+
+        hub.broadcast(&log_message("a", LogLevel::Info, "from-a"));
+        hub.broadcast(&log_message("b", LogLevel::Info, "from-b"));
+
+        let msg = outbox.recv().await.expect("message should arrive");
+        assert_eq!(message_text(msg)["log"]["message"], "from-b");
+        assert!(outbox.state.lock().unwrap().queue.is_empty());
+    }
+
+    #[test]
+    fn ring_buffer_drops_oldest_when_full() {
+        let hub = TelemetryHub::new(2);
+        hub.broadcast(&log_message("dev", LogLevel::Info, "one"));
+        hub.broadcast(&log_message("dev", LogLevel::Info, "two"));
+        hub.broadcast(&log_message("dev", LogLevel::Info, "three"));
+
+        let snapshot = hub.snapshot();
+        let messages: Vec<String> = snapshot
+            .iter()
+            .map(|s| message_text(s.clone())["log"]["message"].to_string())
+            .collect();
+        assert_eq!(messages, vec!["\"two\"", "\"three\""]);
+    }
+
+    #[test]
+    fn broadcast_assigns_increasing_seq() {
+        let hub = TelemetryHub::new(10);
+        hub.broadcast(&log_message("dev", LogLevel::Info, "one"));
+        hub.broadcast(&log_message("dev", LogLevel::Info, "two"));
+
+        let seqs: Vec<u64> = hub
+            .snapshot()
+            .into_iter()
+            .map(|s| message_text(s)["seq"].as_u64().unwrap())
+            .collect();
+        assert_eq!(seqs, vec![1, 2]);
+    }
+
+    #[test]
+    fn replay_since_seq_returns_tail_and_reports_gaps() {
         let hub = TelemetryHub::new(2);
-        hub.broadcast("one".to_string()).await;
-        hub.broadcast("two".to_string()).await;
-        hub.broadcast("three".to_string()).await;
+        for msg in ["one", "two", "three", "four"] {
+            hub.broadcast(&log_message("dev", LogLevel::Info, msg));
+        }
+        let filter = SubscriptionFilter::default();
+
+        let tail = hub.replay(&filter, Some(3), None, &HashSet::new());
+        assert!(tail.gap.is_none());
+        assert_eq!(tail.events.len(), 1);
+        assert!(tail.events[0].contains("four"));
+
+        let evicted = hub.replay(&filter, Some(1), None, &HashSet::new());
+        let gap = evicted.gap.unwrap();
+        assert_eq!((gap.from_seq, gap.to_seq), (Some(2), Some(2)));
+        assert_eq!(gap.reason, GapReason::Evicted);
+        assert_eq!(evicted.events.len(), 2);
+
+        let caught_up = hub.replay(&filter, Some(4), Some(hub.epoch()), &HashSet::new());
+        assert!(caught_up.gap.is_none());
+        assert!(caught_up.events.is_empty());
+
+        // Only the evicted part of the new epoch is reported missing.
+        let reset = hub.replay(&filter, Some(100), None, &HashSet::new());
+        let gap = reset.gap.unwrap();
+        assert_eq!((gap.from_seq, gap.to_seq), (Some(1), Some(2)));
+        assert_eq!(gap.reason, GapReason::Reset);
+        assert_eq!(gap.epoch, Some(hub.epoch()));
+        assert_eq!(reset.events.len(), 2);
+    }
+
+    #[test]
+    fn replay_from_another_epoch_is_a_reset() {
+        let hub = TelemetryHub::new(10);
+        hub.broadcast(&log_message("dev", LogLevel::Info, "one"));
+        hub.broadcast(&log_message("dev", LogLevel::Info, "two"));
+        let filter = SubscriptionFilter::default();
+
+        // The seq is valid in this epoch too, but it was issued before the restart.
+        let replay = hub.replay(&filter, Some(1), Some(hub.epoch() - 1), &HashSet::new());
+        let gap = replay.gap.unwrap();
+        assert_eq!(gap.reason, GapReason::Reset);
+        assert_eq!((gap.from_seq, gap.to_seq), (None, None));
+        assert_eq!(replay.events.len(), 2);
+
+        let json = serde_json::to_value(OutgoingMessage::Gap(gap)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"type": "gap", "reason": "reset", "epoch": hub.epoch()})
+        );
+    }
+
+    #[test]
+    fn replay_skips_seen_ids() {
+        let hub = TelemetryHub::new(10);
+        hub.broadcast(&log_message("dev", LogLevel::Info, "one"));
+        let mark = hub.latest_seq();
+        hub.broadcast(&log_message("dev", LogLevel::Info, "two"));
+        hub.broadcast(&log_message("dev", LogLevel::Info, "three"));
+
+        let seen = HashSet::from(["two".to_string()]);
+        let replay = hub.replay(&SubscriptionFilter::default(), Some(mark), None, &seen);
+        assert_eq!(replay.events.len(), 1);
+        assert!(replay.events[0].contains("three"));
+    }
+
+    #[test]
+    fn persisted_seq_stops_below_the_oldest_pending_write() {
+        let hub = TelemetryHub::new(10);
+        hub.broadcast(&log_message("dev", LogLevel::Info, "one"));
+        let two = hub.broadcast(&log_message("dev", LogLevel::Info, "two"));
+        let three = hub.broadcast(&log_message("dev", LogLevel::Info, "three"));
+        assert_eq!(hub.persisted_seq(), 1);
+
+        drop(three);
+        assert_eq!(hub.persisted_seq(), 1);
+        drop(two);
+        assert_eq!(hub.persisted_seq(), 3);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn subscribe_during_broadcasts_delivers_each_seq_once_in_order() {
+        const TOTAL: u64 = 1000;
+        let config = SlowConsumerConfig {
+            queue_size: TOTAL as usize,
+            ..SlowConsumerConfig::default()
+        };
+        // Large enough that the replay never reports an eviction gap.
+        let hub = Arc::new(TelemetryHub::new(TOTAL as usize).with_slow_consumer(config));
+        hub.broadcast(&location_message("dev", 0));
+
+        let publisher = {
+            let hub = hub.clone();
+            tokio::spawn(async move {
+                for i in 1..TOTAL {
+                    hub.broadcast(&location_message("dev", i));
+                }
+            })
+        };
+        let outbox = hub.new_outbox();
+        hub.subscribe(
+            Uuid::new_v4(),
+            outbox.clone(),
+            SubscriptionFilter::default(),
+            Some(0),
+            None,
+            &HashSet::new(),
+        );
+        publisher.await.unwrap();
+
+        let mut seqs = Vec::with_capacity(TOTAL as usize);
+        for _ in 0..TOTAL {
+            let frame = outbox.recv().await.unwrap();
+            seqs.push(sequenced_seq(&frame).expect("frame should carry seq"));
+        }
+        assert_eq!(seqs, (1..=TOTAL).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn snapshot_applies_filter() {
+        let hub = TelemetryHub::new(10);
+        hub.broadcast(&log_message("dev", LogLevel::Debug, "quiet"));
+        hub.broadcast(&log_message("dev", LogLevel::Error, "loud"));
+
+        let filter = SubscriptionFilter {
+            min_log_level: Some(LogLevel::Warn),
+            ..Default::default()
+        };
+        let snapshot = hub.replay(&filter, None, None, &HashSet::new()).events;
+        assert_eq!(snapshot.len(), 1);
+        assert!(snapshot[0].contains("loud"));
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_sends_gap_notice_before_remaining_frames() {
+        let hub = hub_with_policy(SlowConsumerPolicy::DropOldest, 2);
+        let outbox = hub.new_outbox();
+        hub.add_subscriber(
+            Uuid::new_v4(),
+            outbox.clone(),
+            SubscriptionFilter::default(),
+        );
+
+        for msg in ["one", "two", "three"] {
+            hub.broadcast(&log_message("dev", LogLevel::Info, msg));
+        }
+
+        let gap = message_text(outbox.recv().await.unwrap());
+        assert_eq!(gap["type"], "gap");
+        assert_eq!(gap["reason"], "slow_consumer");
+        assert_eq!(gap["from_seq"], 1);
+        assert_eq!(gap["dropped"], 1);
+        assert_eq!(message_text(outbox.recv().await.unwrap())["seq"], 2);
+        assert_eq!(message_text(outbox.recv().await.unwrap())["seq"], 3);
+        assert_eq!(outbox.dropped(), 1);
+    }
+
+    #[tokio::test]
+    async fn disconnect_policy_closes_after_max_drops() {
+        let hub = hub_with_policy(SlowConsumerPolicy::Disconnect, 1);
+        let outbox = hub.new_outbox();
+        let client_id = Uuid::new_v4();
+        hub.add_subscriber(client_id, outbox.clone(), SubscriptionFilter::default());
+
+        for msg in ["one", "two", "three"] {
+            hub.broadcast(&log_message("dev", LogLevel::Info, msg));
+        }
+
+        assert!(outbox.recv().await.is_none());
+        assert_eq!(outbox.dropped(), 2);
+        // The connection task removes the subscriber; until then it stays closed.
+        hub.broadcast(&log_message("dev", LogLevel::Info, "four"));
+        assert!(outbox.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn subscriber_lagging_past_the_feed_gets_one_gap() {
+        let hub = hub_with_policy(SlowConsumerPolicy::DropOldest, 1);
+        let outbox = hub.new_outbox();
+        hub.add_subscriber(
+            Uuid::new_v4(),
+            outbox.clone(),
+            SubscriptionFilter::default(),
+        );
+
+        let total = 4 * FEED_HEADROOM as u64;
+        for i in 0..total {
+            hub.broadcast(&log_message("dev", LogLevel::Info, &i.to_string()));
+        }
+
+        // Events overwritten in the feed and those dropped by the policy merge into one gap.
+        let gap = message_text(outbox.recv().await.unwrap());
+        assert_eq!(gap["reason"], "slow_consumer");
+        assert_eq!(gap["from_seq"], 1);
+        assert_eq!(gap["to_seq"], total - 1);
+        assert_eq!(gap["dropped"], total - 1);
+        assert_eq!(message_text(outbox.recv().await.unwrap())["seq"], total);
+    }
+
+    #[tokio::test]
+    async fn coalesce_replaces_queued_update_from_same_device() {
+        let hub = hub_with_policy(SlowConsumerPolicy::Coalesce, 2);
+        let outbox = hub.new_outbox();
+        let client_id = Uuid::new_v4();
+        hub.add_subscriber(client_id, outbox.clone(), SubscriptionFilter::default());
+
+        hub.broadcast(&location_message("a", 1));
+        hub.broadcast(&location_message("b", 1));
+        hub.broadcast(&location_message("a", 2));
+
+        // The superseded frame is reported as a gap and the rest arrive in seq order.
+        let gap = message_text(outbox.recv().await.unwrap());
+        assert_eq!(gap["type"], "gap");
+        assert_eq!(
+            (gap["from_seq"].as_u64(), gap["to_seq"].as_u64()),
+            (Some(1), Some(1))
+        );
+        let first = message_text(outbox.recv().await.unwrap());
+        assert_eq!(
+            (first["device"].as_str(), first["seq"].as_u64()),
+            (Some("b"), Some(2))
+        );
+        let second = message_text(outbox.recv().await.unwrap());
+        assert_eq!(
+            (second["device"].as_str(), second["seq"].as_u64()),
+            (Some("a"), Some(3))
+        );
+        assert_eq!(second["timestamp"], 2);
+        assert_eq!(outbox.dropped(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_publishers_reach_many_subscribers_in_order() {
+        const SUBSCRIBERS: usize = 128;
+        const PUBLISHERS: usize = 4;
+        const MESSAGES_PER_PUBLISHER: usize = 500;
+        const TOTAL: usize = PUBLISHERS * MESSAGES_PER_PUBLISHER;
+
+        let hub = Arc::new(hub_with_policy(SlowConsumerPolicy::DropOldest, TOTAL));
+        let readers: Vec<_> = (0..SUBSCRIBERS)
+            .map(|_| {
+                let outbox = hub.new_outbox();
+                hub.add_subscriber(
+                    Uuid::new_v4(),
+                    outbox.clone(),
+                    SubscriptionFilter::default(),
+                );
+                tokio::spawn(async move {
+                    let mut seqs = Vec::with_capacity(TOTAL);
+                    for _ in 0..TOTAL {
+                        let frame = outbox.recv().await.expect("outbox closed early");
+                        seqs.push(sequenced_seq(&frame).expect("frame should carry seq"));
+                    }
+                    (seqs, outbox.dropped())
+                })
+            })
+            .collect();
+
+        let publishers: Vec<_> = (0..PUBLISHERS)
+            .map(|p| {
+                let hub = hub.clone();
+                tokio::spawn(async move {
+                    for i in 0..MESSAGES_PER_PUBLISHER {
+                        hub.broadcast(&location_message(&format!("dev-{p}"), i as u64));
+                    }
+                })
+            })
+            .collect();
+        for publisher in publishers {
+            publisher.await.unwrap();
+        }
+
+        // Every subscriber sees every seq exactly once and in order, so each
+        // publisher's messages also arrive in the order it sent them.
+        let expected: Vec<u64> = (1..=TOTAL as u64).collect();
+        for reader in readers {
+            let (seqs, dropped) = reader.await.unwrap();
+            assert_eq!(seqs, expected);
+            assert_eq!(dropped, 0);
+        }
+        assert_eq!(hub.latest_seq(), TOTAL as u64);
+    }
+
+    /// Fan-out throughput, timed from the first broadcast until every
+    /// subscriber has read every event. Run with
+    /// `cargo test --release fan_out_throughput -- --ignored --nocapture`.
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "benchmark"]
+    async fn fan_out_throughput() {
+        const SUBSCRIBERS: usize = 128;
+        const PUBLISHERS: usize = 4;
+        const MESSAGES_PER_PUBLISHER: usize = 5_000;
+        const TOTAL: usize = PUBLISHERS * MESSAGES_PER_PUBLISHER;
+
+        let hub = Arc::new(hub_with_policy(SlowConsumerPolicy::DropOldest, TOTAL));
+        let readers: Vec<_> = (0..SUBSCRIBERS)
+            .map(|_| {
+                let outbox = hub.new_outbox();
+                hub.add_subscriber(
+                    Uuid::new_v4(),
+                    outbox.clone(),
+                    SubscriptionFilter::default(),
+                );
+                tokio::spawn(async move {
+                    for _ in 0..TOTAL {
+                        outbox.recv().await.expect("outbox closed early");
+                    }
+                    outbox.dropped()
+                })
+            })
+            .collect();
+
+        let started = Instant::now();
+        let publishers: Vec<_> = (0..PUBLISHERS)
+            .map(|p| {
+                let hub = hub.clone();
+                tokio::spawn(async move {
+                    for i in 0..MESSAGES_PER_PUBLISHER {
+                        hub.broadcast(&location_message(&format!("dev-{p}"), i as u64));
+                    }
+                })
+            })
+            .collect();
+        for publisher in publishers {
+            publisher.await.unwrap();
+        }
+        let published = started.elapsed();
+        for reader in readers {
+            assert_eq!(reader.await.unwrap(), 0);
+        }
+        let delivered = started.elapsed();
 
-        let snapshot = hub.snapshot().await;
-        assert_eq!(snapshot, vec!["two".to_string(), "three".to_string()]);
+        let secs = delivered.as_secs_f64();
+        println!(
+            "{TOTAL} events to {SUBSCRIBERS} subscribers: published in {published:?}, \
+             delivered in {delivered:?} ({:.0} events/s, {:.0} frames/s)",
+            TOTAL as f64 / secs,
+            (TOTAL * SUBSCRIBERS) as f64 / secs,
+        );
     }
 }